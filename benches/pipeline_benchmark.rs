@@ -0,0 +1,69 @@
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use fake::faker::internet::raw::*;
+use fake::faker::lorem::raw::*;
+use fake::locales::EN;
+use fake::Fake;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+// Generate log lines with an IPv4 and an IPv6 address each
+fn generate_log(lines: usize) -> String {
+    (0..lines)
+        .map(|_| {
+            let words: Vec<String> = Words(EN, 3..8).fake();
+            let ipv4: String = IPv4(EN).fake();
+            let ipv6: String = IPv6(EN).fake();
+            format!("{} from {} via {}\n", words.join(" "), ipv4, ipv6)
+        })
+        .collect()
+}
+
+// Prepare a config file using the fake database and an input file
+fn setup(lines: usize) -> (PathBuf, PathBuf) {
+    let dir = std::env::temp_dir().join(format!("nali-bench-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("config.toml"), "[geodb.fake_geo]\n").unwrap();
+    let input = dir.join("input.log");
+    fs::write(&input, generate_log(lines)).unwrap();
+    (dir, input)
+}
+
+fn run_binary(dir: &Path, input: &Path, output: &Path, args: &[&str]) {
+    // `--config` works on every platform, unlike pointing the config directory elsewhere
+    let status = Command::new(env!("CARGO_BIN_EXE_nali"))
+        .arg("--config")
+        .arg(dir.join("config.toml"))
+        .args(args)
+        .stdin(File::open(input).unwrap())
+        .stdout(File::create(output).unwrap())
+        .stderr(Stdio::null())
+        .status()
+        .unwrap();
+    assert!(status.success());
+}
+
+fn pipeline_benchmark(c: &mut Criterion) {
+    for lines in [1000, 100000] {
+        let (dir, input) = setup(lines);
+        let output = dir.join("output.log");
+
+        let mut group = c.benchmark_group(format!("binary (lines: {})", lines));
+        group.sample_size(10);
+        group.throughput(Throughput::Bytes(fs::metadata(&input).unwrap().len()));
+
+        group.bench_function("block buffered", |b| {
+            b.iter(|| run_binary(&dir, &input, &output, &[]));
+        });
+
+        group.bench_function("line buffered", |b| {
+            b.iter(|| run_binary(&dir, &input, &output, &["--line-buffered"]));
+        });
+
+        group.finish();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
+
+criterion_group!(benches, pipeline_benchmark);
+criterion_main!(benches);
@@ -0,0 +1,237 @@
+use std::{
+    io::{self, BufWriter, ErrorKind, IsTerminal, Write},
+    path::PathBuf,
+    time::Duration,
+};
+
+use clap::Parser as _;
+
+use crate::{
+    commands,
+    config::{GeoDBConfig, NaliConfig, ParserConfig},
+    diag::{self, Verbosity},
+    error::{NaliError, Result},
+    registry::Registry,
+    stream::{self, LineEndings},
+    token::{KindSet, TokenKind},
+};
+
+const OUTPUT_BUFFER_SIZE: usize = 64 * 1024;
+
+#[derive(clap::Parser)]
+#[command(about, disable_version_flag = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Print the version and the configured database
+    #[arg(short = 'V', long)]
+    version: bool,
+
+    /// Text to annotate, read from stdin when absent
+    #[arg(value_name = "QUERY")]
+    queries: Vec<String>,
+
+    /// Only report errors on stderr
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Also report informational messages on stderr
+    #[arg(short, long, global = true)]
+    verbose: bool,
+
+    /// Config file to read instead of the default one
+    #[arg(long, global = true, value_name = "PATH")]
+    config: Option<PathBuf>,
+
+    /// Parser to use, e.g. `fast_parser` or `regex_parser`
+    #[arg(long, global = true, value_name = "NAME")]
+    parser: Option<String>,
+
+    /// Database to use, e.g. `geo_lite2`
+    #[arg(long, global = true, value_name = "NAME")]
+    db: Option<String>,
+
+    /// Token kinds to detect, e.g. `ipv4,ipv6`
+    #[arg(long, value_delimiter = ',', value_name = "KINDS")]
+    detect: Option<Vec<String>>,
+
+    /// Detected token kinds to annotate, the others are left as plain text
+    #[arg(long, value_delimiter = ',', value_name = "KINDS")]
+    annotate: Option<Vec<String>>,
+
+    /// How line terminators are written back
+    #[arg(long, value_enum, value_name = "MODE")]
+    line_endings: Option<LineEndings>,
+
+    /// Print partial lines as they arrive, for live tools like ping or mtr
+    #[arg(long)]
+    stream: bool,
+
+    /// How long to wait before printing a held back partial address in stream mode
+    #[arg(long, value_name = "MS")]
+    stream_timeout: Option<u64>,
+
+    /// Flush after every line even when the output is not a terminal
+    #[arg(long)]
+    line_buffered: bool,
+}
+
+#[derive(clap::Subcommand)]
+enum Command {
+    /// Inspect the configured database
+    Db {
+        #[command(subcommand)]
+        command: DbCommand,
+    },
+    /// Manage the config file
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+}
+
+#[derive(clap::Subcommand)]
+enum DbCommand {
+    /// Show the database file and its metadata
+    Info,
+    /// Check that the database opens and every record decodes
+    Verify,
+    /// Download the database and replace the current file once the new one opens
+    Update {
+        /// Download from this URL instead of the configured `update_url`
+        #[arg(long)]
+        url: Option<String>,
+    },
+}
+
+#[derive(clap::Subcommand)]
+enum ConfigCommand {
+    /// Print the effective configuration
+    Show,
+    /// Write a config file with the default settings
+    Init {
+        /// Overwrite an existing config file
+        #[arg(long)]
+        force: bool,
+    },
+    /// Check the config file and build the configured parser and database
+    Validate,
+}
+
+fn kind_set(kinds: Vec<String>) -> KindSet {
+    KindSet::only(kinds.into_iter().map(TokenKind::from).collect())
+}
+
+// The command-line entry point, embedders can register extra parsers and
+// databases before handing the registry over
+pub fn run(registry: Registry) -> Result<()> {
+    let cli = Cli::parse();
+    diag::set_verbosity(if cli.quiet {
+        Verbosity::Quiet
+    } else if cli.verbose {
+        Verbosity::Verbose
+    } else {
+        Verbosity::Normal
+    });
+
+    // The config file may not exist or be broken yet
+    if let Some(Command::Config {
+        command: ConfigCommand::Init { force },
+    }) = cli.command
+    {
+        return commands::config_init(cli.config.as_deref(), force);
+    }
+
+    if cli.version {
+        return commands::version(load_config(&cli), &registry);
+    }
+
+    let nali_config = load_config(&cli)?;
+    let result = match &cli.command {
+        Some(Command::Db { command }) => match command {
+            DbCommand::Info => commands::db_info(&nali_config, &registry),
+            DbCommand::Verify => commands::db_verify(&nali_config, &registry),
+            DbCommand::Update { url } => commands::db_update(&nali_config, url.as_deref()),
+        },
+        Some(Command::Config { command }) => match command {
+            ConfigCommand::Show => commands::config_show(&nali_config),
+            ConfigCommand::Validate => commands::config_validate(&nali_config, &registry),
+            ConfigCommand::Init { .. } => unreachable!("handled before loading the config"),
+        },
+        None => annotate(cli, nali_config, registry),
+    };
+    match result {
+        // The reader went away, e.g. `nali < log | head`
+        Err(NaliError::Io(e)) if e.kind() == ErrorKind::BrokenPipe => Ok(()),
+        result => result,
+    }
+}
+
+// Load the config file and apply the overrides from the command line
+fn load_config(cli: &Cli) -> Result<NaliConfig> {
+    let mut nali_config = NaliConfig::load(cli.config.as_deref())?;
+    if let Some(name) = &cli.parser {
+        nali_config.set_parser(ParserConfig::from(name.clone()));
+    }
+    // Keep the options from the config file when it already uses this database
+    if let Some(name) = cli
+        .db
+        .as_ref()
+        .filter(|name| *name != nali_config.geodb().name())
+    {
+        nali_config.set_geodb(GeoDBConfig::from_name(name));
+    }
+    Ok(nali_config)
+}
+
+fn annotate(cli: Cli, nali_config: NaliConfig, registry: Registry) -> Result<()> {
+    let geo = registry.build_geodb(&nali_config.geodb(), &nali_config)?;
+    if let Some(metadata) = geo.metadata() {
+        commands::warn_if_outdated(&metadata, &nali_config);
+    }
+    let mut parser_options = nali_config.parser_options()?;
+    if let Some(kinds) = cli.detect {
+        parser_options.detect = kind_set(kinds);
+    }
+    if let Some(kinds) = cli.annotate {
+        parser_options.annotate = kind_set(kinds);
+    }
+    parser_options.check_kinds()?;
+    let parser = registry.build_parser(nali_config.parser().name(), parser_options)?;
+
+    if !cli.queries.is_empty() {
+        let mut stdout = io::stdout().lock();
+        for query in &cli.queries {
+            writeln!(stdout, "{}", parser.parse(query, &geo).colorize())?;
+        }
+        return Ok(());
+    }
+
+    let stdin = io::stdin();
+    if cli.stream || nali_config.stream() {
+        let timeout = cli
+            .stream_timeout
+            .map(Duration::from_millis)
+            .unwrap_or(nali_config.stream_timeout());
+        stream::annotate_stream(parser.as_ref(), &geo, stdin, io::stdout().lock(), timeout)?;
+    } else {
+        let preserve_terminators = cli
+            .line_endings
+            .unwrap_or(nali_config.line_endings())
+            .preserves(stdin.is_terminal());
+        // Flushing every line costs a syscall per line, only do it when someone is watching
+        let stdout = io::stdout();
+        let line_buffered = cli.line_buffered || stdout.is_terminal();
+        stream::annotate_lines(
+            parser.as_ref(),
+            &geo,
+            stdin.lock(),
+            BufWriter::with_capacity(OUTPUT_BUFFER_SIZE, stdout.lock()),
+            preserve_terminators,
+            line_buffered,
+        )?;
+    }
+
+    Ok(())
+}
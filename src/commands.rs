@@ -0,0 +1,273 @@
+use std::{
+    fs,
+    io::{self, Write},
+    net::IpAddr,
+    path::Path,
+};
+
+use ipnetwork::IpNetwork;
+use maxminddb::Reader;
+use serde::de::IgnoredAny;
+
+use crate::{
+    config::{GeoDBConfig, NaliConfig},
+    diag,
+    error::{NaliError, Result},
+    geo::geodb::{civil_from_epoch, DbMetadata, SECONDS_PER_DAY},
+    registry::Registry,
+    update,
+};
+
+// Addresses looked up to check that a database answers queries at all
+const SAMPLE_IPS: [&str; 4] = [
+    "1.1.1.1",
+    "8.8.8.8",
+    "2606:4700:4700::1111",
+    "2001:4860::8888",
+];
+
+// Format a Unix timestamp as a UTC date
+fn format_date(epoch: u64) -> String {
+    let (year, month, day) = civil_from_epoch(epoch);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+fn open_mmdb(path: &str) -> Result<Reader<Vec<u8>>> {
+    Reader::open_readfile(path)
+        .map_err(|e| NaliError::Database(format!("Failed to open {}: {}", path, e)))
+}
+
+// Warn on stderr when the database is older than the configured `max_age_days`
+pub fn warn_if_outdated(metadata: &DbMetadata, nali_config: &NaliConfig) {
+    let Some(max_age) = nali_config.max_age() else {
+        return;
+    };
+    let age = metadata.age();
+    if age > max_age {
+        // Only MaxMind databases can be updated by nali itself
+        let advice = match nali_config.geodb() {
+            GeoDBConfig::GeoLite2(_) => "run `nali db update` to refresh it",
+            _ => "consider replacing it with a newer release",
+        };
+        diag::warn(format!(
+            "The database was built on {}, {} days ago, {}",
+            format_date(metadata.build_epoch),
+            age.as_secs() / SECONDS_PER_DAY,
+            advice
+        ));
+    }
+}
+
+fn write_metadata(out: &mut impl Write, metadata: &DbMetadata) -> Result<()> {
+    writeln!(out, "Type:         {}", metadata.database_type)?;
+    writeln!(
+        out,
+        "Built:        {} (epoch {}, {} days ago)",
+        format_date(metadata.build_epoch),
+        metadata.build_epoch,
+        metadata.age().as_secs() / SECONDS_PER_DAY
+    )?;
+    writeln!(out, "IP version:   {}", metadata.ip_version)?;
+    writeln!(out, "Node count:   {}", metadata.node_count)?;
+    writeln!(out, "Languages:    {}", metadata.languages.join(", "))?;
+    Ok(())
+}
+
+// `nali db info`
+pub fn db_info(nali_config: &NaliConfig, registry: &Registry) -> Result<()> {
+    let mut out = io::stdout().lock();
+    let geodb = nali_config.geodb();
+    writeln!(out, "Database:     {}", geodb.name())?;
+    let GeoDBConfig::GeoLite2(geolite2_config) = geodb else {
+        let db = registry.build_geodb(&geodb, nali_config)?;
+        match db.metadata() {
+            Some(metadata) => {
+                write_metadata(&mut out, &metadata)?;
+                warn_if_outdated(&metadata, nali_config);
+            }
+            None => writeln!(out, "No file metadata available for this database")?,
+        }
+        return Ok(());
+    };
+
+    let path = geolite2_config.full_path(nali_config.app_support_path());
+    let size = fs::metadata(&path)
+        .map_err(|e| NaliError::Database(format!("Failed to read {}: {}", path, e)))?
+        .len();
+    let metadata = open_mmdb(&path)?.metadata;
+    writeln!(out, "Path:         {}", path)?;
+    writeln!(out, "Size:         {} bytes", size)?;
+    write_metadata(&mut out, &DbMetadata::from(&metadata))?;
+    writeln!(out, "Record size:  {} bits", metadata.record_size)?;
+    writeln!(
+        out,
+        "Format:       {}.{}",
+        metadata.binary_format_major_version, metadata.binary_format_minor_version
+    )?;
+    if let Some(description) = metadata.description.get("en") {
+        writeln!(out, "Description:  {}", description)?;
+    }
+    warn_if_outdated(&DbMetadata::from(&metadata), nali_config);
+    Ok(())
+}
+
+// `nali --version`, the database is described on a best effort basis
+pub fn version(nali_config: Result<NaliConfig>, registry: &Registry) -> Result<()> {
+    let mut out = io::stdout().lock();
+    writeln!(out, "nali {}", env!("CARGO_PKG_VERSION"))?;
+    let nali_config = match nali_config {
+        Ok(nali_config) => nali_config,
+        Err(e) => {
+            writeln!(out, "Database: unknown ({})", e)?;
+            return Ok(());
+        }
+    };
+    let geodb = nali_config.geodb();
+    match registry.build_geodb(&geodb, &nali_config) {
+        Ok(db) => match db.metadata() {
+            Some(metadata) => {
+                writeln!(
+                    out,
+                    "Database: {} ({}, built {})",
+                    geodb.name(),
+                    metadata.database_type,
+                    format_date(metadata.build_epoch)
+                )?;
+                warn_if_outdated(&metadata, &nali_config);
+            }
+            None => writeln!(out, "Database: {}", geodb.name())?,
+        },
+        Err(e) => writeln!(out, "Database: {} ({})", geodb.name(), e)?,
+    }
+    Ok(())
+}
+
+// `nali db verify`, walks every network of a MaxMind database and decodes its record
+pub fn db_verify(nali_config: &NaliConfig, registry: &Registry) -> Result<()> {
+    let mut out = io::stdout().lock();
+    let geodb = nali_config.geodb();
+    if let GeoDBConfig::GeoLite2(geolite2_config) = &geodb {
+        let path = geolite2_config.full_path(nali_config.app_support_path());
+        let reader = open_mmdb(&path)?;
+        if reader.metadata.binary_format_major_version != 2 {
+            return Err(NaliError::Database(format!(
+                "Unsupported binary format version {} in {}",
+                reader.metadata.binary_format_major_version, path
+            )));
+        }
+        let everything: IpNetwork = if reader.metadata.ip_version == 4 {
+            "0.0.0.0/0".parse()
+        } else {
+            "::/0".parse()
+        }
+        .expect("valid network");
+        let corrupt = |e| NaliError::Database(format!("{} is corrupt: {}", path, e));
+        let mut networks = 0;
+        for item in reader.within::<IgnoredAny>(everything).map_err(corrupt)? {
+            item.map_err(corrupt)?;
+            networks += 1;
+        }
+        writeln!(out, "OK: {} ({} networks)", path, networks)?;
+        return Ok(());
+    }
+
+    // Other databases can only be checked by querying them
+    let db = registry.build_geodb(&geodb, nali_config)?;
+    let answered = SAMPLE_IPS
+        .iter()
+        .filter(|ip| ip.parse::<IpAddr>().is_ok() && db.lookup(ip).is_some())
+        .count();
+    writeln!(
+        out,
+        "OK: {} answered {} of {} sample lookups",
+        geodb.name(),
+        answered,
+        SAMPLE_IPS.len()
+    )?;
+    Ok(())
+}
+
+// `nali db update`, downloads the database and swaps it in once it opens
+pub fn db_update(nali_config: &NaliConfig, url: Option<&str>) -> Result<()> {
+    let mut out = io::stdout().lock();
+    let GeoDBConfig::GeoLite2(geolite2_config) = nali_config.geodb() else {
+        return Err(NaliError::Config(format!(
+            "{} does not support updates",
+            nali_config.geodb().name()
+        )));
+    };
+    let url = url
+        .map(str::to_string)
+        .or_else(|| geolite2_config.update_url.clone())
+        .ok_or_else(|| {
+            NaliError::Config("No update_url configured, pass --url to choose one".to_string())
+        })?;
+    let sha256_url = geolite2_config
+        .sha256_url
+        .clone()
+        .unwrap_or_else(|| format!("{}.sha256", url));
+    let target = geolite2_config.full_path(nali_config.app_support_path());
+    let file_name = Path::new(&geolite2_config.path)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or(&geolite2_config.path)
+        .to_string();
+
+    diag::info(format!("Downloading {}", url));
+    let download = update::fetch(&url)?;
+    update::verify_sha256(&download, &sha256_url)?;
+    let database = update::extract(download, &url, &file_name)?;
+    update::install(&database, Path::new(&target), |path| {
+        Reader::open_readfile(path).map(|_| ()).map_err(|e| {
+            NaliError::Database(format!("The downloaded database does not open: {}", e))
+        })
+    })?;
+    writeln!(out, "Updated {} from {}", target, url)?;
+    Ok(())
+}
+
+// `nali config show`
+pub fn config_show(nali_config: &NaliConfig) -> Result<()> {
+    let mut out = io::stdout().lock();
+    match nali_config.config_path() {
+        Some(path) if path.exists() => writeln!(out, "# Loaded from {}", path.display()),
+        _ => writeln!(out, "# No config file, showing defaults"),
+    }?;
+    write!(out, "{}", nali_config.to_toml()?)?;
+    Ok(())
+}
+
+// `nali config init`
+pub fn config_init(nali_config_path: Option<&Path>, force: bool) -> Result<()> {
+    let mut out = io::stdout().lock();
+    let path = NaliConfig::locate(nali_config_path).ok_or_else(|| {
+        NaliError::Config("No config directory, pass --config to choose a path".to_string())
+    })?;
+    if path.exists() && !force {
+        return Err(NaliError::Config(format!(
+            "{} already exists, pass --force to overwrite it",
+            path.display()
+        )));
+    }
+    let write_error =
+        |e: std::io::Error| NaliError::Config(format!("Failed to write {}: {}", path.display(), e));
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(write_error)?;
+    }
+    fs::write(&path, NaliConfig::default_toml()?).map_err(write_error)?;
+    writeln!(out, "Wrote {}", path.display())?;
+    Ok(())
+}
+
+// `nali config validate`, loading already checked the syntax, this builds everything
+pub fn config_validate(nali_config: &NaliConfig, registry: &Registry) -> Result<()> {
+    let mut out = io::stdout().lock();
+    let parser_options = nali_config.parser_options()?;
+    registry.build_parser(nali_config.parser().name(), parser_options)?;
+    registry.build_geodb(&nali_config.geodb(), nali_config)?;
+    match nali_config.config_path() {
+        Some(path) if path.exists() => writeln!(out, "OK: {}", path.display()),
+        _ => writeln!(out, "OK: default configuration"),
+    }?;
+    Ok(())
+}
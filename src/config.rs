@@ -1,36 +1,133 @@
-use serde::Deserialize;
-use std::{error::Error, fs};
+use serde::{Deserialize, Serialize};
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use crate::{
+    custom::CustomPattern,
+    diag,
+    error::{NaliError, Result},
+    filter::{AnnotationFilter, FilterRules},
+    geo::geodb::SECONDS_PER_DAY,
+    overlap::OverlapStrategy,
+    parser::ParserOptions,
+    stream::LineEndings,
+    template::Template,
+    token::KindSet,
+};
+
+const DEFAULT_STREAM_TIMEOUT_MS: u64 = 100;
+const DEFAULT_MAX_AGE_DAYS: u64 = 90;
 
 #[derive(Debug)]
 pub struct NaliConfig {
     app_support_path: String,
+    config_path: Option<PathBuf>,
     toml_config: TomlConfig,
 }
 
+// The built-in defaults, without reading any file or environment variable
 impl Default for NaliConfig {
     fn default() -> Self {
-        Self::new()
+        Self {
+            app_support_path: ".".to_string(),
+            config_path: None,
+            toml_config: TomlConfig::default(),
+        }
     }
 }
 
 impl NaliConfig {
-    pub fn new() -> Self {
-        let app_support_path = dirs::config_dir().unwrap().join("nali-rs");
-
-        // Use the default configuration if the config file is missing
-        let toml_config =
-            match TomlConfig::read_config(app_support_path.join("config.toml").to_str().unwrap()) {
-                Ok(config) => config,
-                Err(e) => {
-                    println!("Failed to read config file: {}", e);
-                    println!("Using default configuration");
-                    TomlConfig::default()
-                }
-            };
+    pub fn new() -> Result<Self> {
+        Self::load(None)
+    }
 
-        Self {
-            app_support_path: app_support_path.to_str().unwrap().to_string(),
+    // Settings are taken, from highest to lowest precedence, from the environment,
+    // the config file and the defaults. The config file is `path` if given, then
+    // `$NALI_CONFIG`, then `nali-rs/config.toml` in the user config directory.
+    // Relative paths in the config are resolved against the config file's directory.
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let explicit_path = Self::explicit_path(path);
+        let config_path = Self::locate(path);
+
+        // Fall back to the current directory when there is no config directory
+        let app_support_path = config_path
+            .as_deref()
+            .and_then(Path::parent)
+            .map(|dir| dir.to_string_lossy().into_owned())
+            .unwrap_or_else(|| ".".to_string());
+
+        // Use the default configuration if the default config file is missing,
+        // a file that was asked for explicitly has to exist
+        let toml_config = match &config_path {
+            Some(config_path) if explicit_path.is_some() || config_path.exists() => {
+                diag::info(format!("Reading config file {}", config_path.display()));
+                TomlConfig::read_config(config_path)?
+            }
+            Some(config_path) => {
+                diag::info(format!(
+                    "No config file at {}, using default configuration",
+                    config_path.display()
+                ));
+                TomlConfig::default()
+            }
+            None => {
+                diag::info("No config directory, using default configuration");
+                TomlConfig::default()
+            }
+        };
+
+        let mut nali_config = Self {
+            app_support_path,
+            config_path,
             toml_config,
+        };
+        nali_config.apply_env();
+        Ok(nali_config)
+    }
+
+    fn explicit_path(path: Option<&Path>) -> Option<PathBuf> {
+        path.map(Path::to_path_buf)
+            .or_else(|| env::var_os("NALI_CONFIG").map(PathBuf::from))
+    }
+
+    // The config file `load` reads, whether it exists or not
+    pub fn locate(path: Option<&Path>) -> Option<PathBuf> {
+        Self::explicit_path(path)
+            .or_else(|| dirs::config_dir().map(|dir| dir.join("nali-rs").join("config.toml")))
+    }
+
+    // The config file this config was loaded from, if there is a config location at all
+    pub fn config_path(&self) -> Option<&Path> {
+        self.config_path.as_deref()
+    }
+
+    // The effective settings in config file format
+    pub fn to_toml(&self) -> Result<String> {
+        toml::to_string_pretty(&self.toml_config)
+            .map_err(|e| NaliError::Config(format!("Failed to serialize config: {}", e)))
+    }
+
+    // The built-in defaults in config file format
+    pub fn default_toml() -> Result<String> {
+        Self::default().to_toml()
+    }
+
+    // Override single settings with `NALI_PARSER`, `NALI_GEODB` and `NALI_GEODB_PATH`
+    fn apply_env(&mut self) {
+        if let Ok(name) = env::var("NALI_PARSER") {
+            self.set_parser(ParserConfig::from(name));
+        }
+        if let Ok(name) = env::var("NALI_GEODB") {
+            if name != self.toml_config.geodb.name() {
+                self.set_geodb(GeoDBConfig::from_name(&name));
+            }
+        }
+        if let Ok(path) = env::var("NALI_GEODB_PATH") {
+            self.toml_config.geodb.set_path(path);
         }
     }
 
@@ -38,44 +135,220 @@ impl NaliConfig {
         self.toml_config.parser.clone()
     }
 
+    pub fn set_parser(&mut self, parser: ParserConfig) {
+        self.toml_config.parser = parser;
+    }
+
+    pub fn parser_options(&self) -> Result<ParserOptions> {
+        let custom = CustomPattern::load_all(&self.toml_config.custom, &self.app_support_path)?;
+        let filter = AnnotationFilter::new(
+            FilterRules::new(&self.toml_config.ignore)?,
+            FilterRules::new(&self.toml_config.allow)?,
+        );
+        let parser_options = ParserOptions {
+            overlap: self.toml_config.overlap.clone(),
+            custom,
+            filter,
+            detect: self.toml_config.detect.clone(),
+            annotate: self.toml_config.annotate.clone(),
+            template: self.template()?.map(Arc::new),
+        };
+        parser_options.check_kinds()?;
+        Ok(parser_options)
+    }
+
+    pub fn template(&self) -> Result<Option<Template>> {
+        self.toml_config
+            .template
+            .as_deref()
+            .map(|template| template.parse().map_err(NaliError::Config))
+            .transpose()
+    }
+
+    pub fn line_endings(&self) -> LineEndings {
+        self.toml_config.line_endings
+    }
+
+    pub fn stream(&self) -> bool {
+        self.toml_config.stream
+    }
+
+    pub fn stream_timeout(&self) -> Duration {
+        Duration::from_millis(
+            self.toml_config
+                .stream_timeout_ms
+                .unwrap_or(DEFAULT_STREAM_TIMEOUT_MS),
+        )
+    }
+
+    // Databases built longer ago than this are reported as outdated, `max_age_days = 0`
+    // turns the check off
+    pub fn max_age(&self) -> Option<Duration> {
+        match self
+            .toml_config
+            .max_age_days
+            .unwrap_or(DEFAULT_MAX_AGE_DAYS)
+        {
+            0 => None,
+            days => Some(Duration::from_secs(days.saturating_mul(SECONDS_PER_DAY))),
+        }
+    }
+
     pub fn geodb(&self) -> GeoDBConfig {
         self.toml_config.geodb.clone()
     }
 
+    pub fn set_geodb(&mut self, geodb: GeoDBConfig) {
+        self.toml_config.geodb = geodb;
+    }
+
     pub fn app_support_path(&self) -> &str {
         &self.app_support_path
     }
 }
 
+// Resolve a path from the config file, absolute paths are kept as they are
+fn resolve_path(app_support_path: &str, path: &str) -> String {
+    Path::new(app_support_path)
+        .join(path)
+        .to_string_lossy()
+        .into_owned()
+}
+
 // TOML Config
-#[derive(Deserialize, Debug, Default)]
+#[derive(Deserialize, Serialize, Debug, Default)]
 #[serde(default, deny_unknown_fields)]
 struct TomlConfig {
     parser: ParserConfig,
+    overlap: OverlapStrategy,
+    custom: Vec<CustomPatternConfig>,
+    ignore: FilterRulesConfig,
+    allow: FilterRulesConfig,
+    detect: KindSet,
+    annotate: KindSet,
+    template: Option<String>,
+    line_endings: LineEndings,
+    stream: bool,
+    stream_timeout_ms: Option<u64>,
+    max_age_days: Option<u64>,
     geodb: GeoDBConfig,
 }
 
 impl TomlConfig {
-    fn read_config(path: &str) -> Result<TomlConfig, Box<dyn Error>> {
-        let contents = fs::read_to_string(path)?;
-        let config: TomlConfig = toml::from_str(&contents)?;
+    fn read_config(path: &Path) -> Result<TomlConfig> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| NaliError::Config(format!("Failed to read {}: {}", path.display(), e)))?;
+        let config: TomlConfig = toml::from_str(&contents)
+            .map_err(|e| NaliError::Config(format!("Invalid {}: {}", path.display(), e)))?;
         Ok(config)
     }
 }
 
-#[derive(Deserialize, Debug, Clone, Default)]
-#[serde(rename_all = "snake_case", deny_unknown_fields)]
+// Parsers are looked up by name in the registry
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq)]
+#[serde(from = "String", into = "String")]
 pub enum ParserConfig {
     #[default]
     FastParser,
     RegexParser,
+    // A parser registered by an embedder
+    Custom(String),
+}
+
+impl From<String> for ParserConfig {
+    fn from(name: String) -> Self {
+        match name.as_str() {
+            "fast_parser" => ParserConfig::FastParser,
+            "regex_parser" => ParserConfig::RegexParser,
+            _ => ParserConfig::Custom(name),
+        }
+    }
 }
 
-#[derive(Deserialize, Debug, Clone)]
+impl From<ParserConfig> for String {
+    fn from(parser: ParserConfig) -> Self {
+        parser.name().to_string()
+    }
+}
+
+impl ParserConfig {
+    pub fn name(&self) -> &str {
+        match self {
+            ParserConfig::FastParser => "fast_parser",
+            ParserConfig::RegexParser => "regex_parser",
+            ParserConfig::Custom(name) => name,
+        }
+    }
+}
+
+// Databases are looked up by name in the registry
+#[derive(Deserialize, Serialize, Debug, Clone)]
 #[serde(rename_all = "snake_case", deny_unknown_fields)]
 pub enum GeoDBConfig {
     GeoLite2(GeoLite2Config),
+    #[serde(rename = "geo_lite2_asn")]
+    GeoLite2ASN(GeoLite2ASNConfig),
+    #[serde(rename = "qqwry")]
+    QQWry(QQWryConfig),
+    #[serde(rename = "zxipv6wry")]
+    ZXIPv6Wry(ZXIPv6WryConfig),
+    #[serde(rename = "ip2region")]
+    Ip2Region(Ip2RegionConfig),
+    #[serde(rename = "ip2location")]
+    IP2Location(IP2LocationConfig),
     FakeGeo(FakeGeoConfig),
+    Composite(CompositeConfig),
+    Custom(CustomGeoDBConfig),
+}
+
+impl GeoDBConfig {
+    // The default config of the database with the given name
+    pub fn from_name(name: &str) -> Self {
+        match name {
+            "geo_lite2" => GeoDBConfig::GeoLite2(GeoLite2Config::default()),
+            "geo_lite2_asn" => GeoDBConfig::GeoLite2ASN(GeoLite2ASNConfig::default()),
+            "qqwry" => GeoDBConfig::QQWry(QQWryConfig::default()),
+            "zxipv6wry" => GeoDBConfig::ZXIPv6Wry(ZXIPv6WryConfig::default()),
+            "ip2region" => GeoDBConfig::Ip2Region(Ip2RegionConfig::default()),
+            "ip2location" => GeoDBConfig::IP2Location(IP2LocationConfig::default()),
+            "fake_geo" => GeoDBConfig::FakeGeo(FakeGeoConfig::default()),
+            "composite" => GeoDBConfig::Composite(CompositeConfig::default()),
+            _ => GeoDBConfig::Custom(CustomGeoDBConfig {
+                name: name.to_string(),
+                options: toml::Table::new(),
+            }),
+        }
+    }
+
+    // Point a file-based database at `path`, databases without a file ignore it
+    pub fn set_path(&mut self, path: String) {
+        match self {
+            GeoDBConfig::GeoLite2(config) => config.path = path,
+            GeoDBConfig::GeoLite2ASN(config) => config.path = path,
+            GeoDBConfig::QQWry(config) => config.path = path,
+            GeoDBConfig::ZXIPv6Wry(config) => config.path = path,
+            GeoDBConfig::Ip2Region(config) => config.path = path,
+            GeoDBConfig::IP2Location(config) => config.path = path,
+            GeoDBConfig::FakeGeo(_) | GeoDBConfig::Composite(_) => {}
+            GeoDBConfig::Custom(config) => {
+                config.options.insert("path".to_string(), path.into());
+            }
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            GeoDBConfig::GeoLite2(_) => "geo_lite2",
+            GeoDBConfig::GeoLite2ASN(_) => "geo_lite2_asn",
+            GeoDBConfig::QQWry(_) => "qqwry",
+            GeoDBConfig::ZXIPv6Wry(_) => "zxipv6wry",
+            GeoDBConfig::Ip2Region(_) => "ip2region",
+            GeoDBConfig::IP2Location(_) => "ip2location",
+            GeoDBConfig::FakeGeo(_) => "fake_geo",
+            GeoDBConfig::Composite(_) => "composite",
+            GeoDBConfig::Custom(config) => &config.name,
+        }
+    }
 }
 
 impl Default for GeoDBConfig {
@@ -84,23 +357,209 @@ impl Default for GeoDBConfig {
     }
 }
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct GeoLite2Config {
     pub path: String,
+    // Where `nali db update` downloads the database from
+    pub update_url: Option<String>,
+    // The SHA-256 sidecar of the download, `update_url` + `.sha256` by default
+    pub sha256_url: Option<String>,
+    // A GeoLite2-ASN or GeoIP2-ISP database to fill in the ASN fields
+    pub asn_path: Option<String>,
 }
 
 impl Default for GeoLite2Config {
     fn default() -> Self {
         GeoLite2Config {
             path: "GeoLite2-City.mmdb".to_string(),
+            update_url: None,
+            sha256_url: None,
+            asn_path: None,
         }
     }
 }
 
 impl GeoLite2Config {
     pub fn full_path(&self, app_support_path: &str) -> String {
-        format!("{}/{}", app_support_path, self.path)
+        resolve_path(app_support_path, &self.path)
+    }
+
+    pub fn full_asn_path(&self, app_support_path: &str) -> Option<String> {
+        self.asn_path
+            .as_ref()
+            .map(|asn_path| resolve_path(app_support_path, asn_path))
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct GeoLite2ASNConfig {
+    pub path: String,
+}
+
+impl Default for GeoLite2ASNConfig {
+    fn default() -> Self {
+        GeoLite2ASNConfig {
+            path: "GeoLite2-ASN.mmdb".to_string(),
+        }
+    }
+}
+
+impl GeoLite2ASNConfig {
+    pub fn full_path(&self, app_support_path: &str) -> String {
+        resolve_path(app_support_path, &self.path)
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct QQWryConfig {
+    pub path: String,
+}
+
+impl Default for QQWryConfig {
+    fn default() -> Self {
+        QQWryConfig {
+            path: "qqwry.dat".to_string(),
+        }
+    }
+}
+
+impl QQWryConfig {
+    pub fn full_path(&self, app_support_path: &str) -> String {
+        resolve_path(app_support_path, &self.path)
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ZXIPv6WryConfig {
+    pub path: String,
+}
+
+impl Default for ZXIPv6WryConfig {
+    fn default() -> Self {
+        ZXIPv6WryConfig {
+            path: "ipv6wry.db".to_string(),
+        }
+    }
+}
+
+impl ZXIPv6WryConfig {
+    pub fn full_path(&self, app_support_path: &str) -> String {
+        resolve_path(app_support_path, &self.path)
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Ip2RegionConfig {
+    pub path: String,
+    #[serde(default)]
+    pub mode: Ip2RegionMode,
+}
+
+impl Default for Ip2RegionConfig {
+    fn default() -> Self {
+        Ip2RegionConfig {
+            path: "ip2region.xdb".to_string(),
+            mode: Ip2RegionMode::default(),
+        }
+    }
+}
+
+impl Ip2RegionConfig {
+    pub fn full_path(&self, app_support_path: &str) -> String {
+        resolve_path(app_support_path, &self.path)
     }
 }
-#[derive(Deserialize, Debug, Clone, Default)]
+
+// How much of an ip2region file is kept in memory
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Ip2RegionMode {
+    // Every lookup reads the file, nothing is cached
+    File,
+    // The 512 KiB vector index is cached, segments and regions are read from the file
+    VectorIndex,
+    // The whole file is loaded, lookups never touch the file
+    #[default]
+    Memory,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct IP2LocationConfig {
+    pub path: String,
+}
+
+impl Default for IP2LocationConfig {
+    fn default() -> Self {
+        IP2LocationConfig {
+            path: "IP2LOCATION-LITE-DB11.IPV6.BIN".to_string(),
+        }
+    }
+}
+
+impl IP2LocationConfig {
+    pub fn full_path(&self, app_support_path: &str) -> String {
+        resolve_path(app_support_path, &self.path)
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
 pub struct FakeGeoConfig {}
+
+// Several databases asked in order
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct CompositeConfig {
+    pub strategy: CompositeStrategy,
+    pub backends: Vec<CompositeBackendConfig>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum CompositeStrategy {
+    // The first backend that knows the address answers
+    #[default]
+    FirstHit,
+    // Every backend is asked, earlier backends win fields they both know
+    Merge,
+}
+
+// `db` is a table of its own, flattening it would lose `deny_unknown_fields`
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct CompositeBackendConfig {
+    // Only ask this backend about IPv4 (4) or IPv6 (6) addresses
+    pub ip_version: Option<u8>,
+    pub db: GeoDBConfig,
+}
+
+// A database registered by an embedder, the remaining keys are passed to its factory
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct CustomGeoDBConfig {
+    pub name: String,
+    #[serde(flatten)]
+    pub options: toml::Table,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct CustomPatternConfig {
+    pub name: String,
+    pub pattern: String,
+    pub source: Option<String>,
+}
+
+impl CustomPatternConfig {
+    pub fn full_source_path(&self, app_support_path: &str) -> Option<String> {
+        self.source
+            .as_ref()
+            .map(|source| resolve_path(app_support_path, source))
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct FilterRulesConfig {
+    pub cidrs: Vec<String>,
+    pub domains: Vec<String>,
+    pub regexes: Vec<String>,
+}
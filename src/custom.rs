@@ -0,0 +1,140 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    sync::Arc,
+};
+
+use regex::Regex;
+
+use crate::{
+    config::CustomPatternConfig,
+    error::{NaliError, Result},
+    token::TokenKind,
+};
+
+// A user-defined token pattern with the values used to annotate its matches
+#[derive(Debug, Clone)]
+pub struct CustomPattern {
+    name: String,
+    regex: Regex,
+    values: Arc<HashMap<String, String>>,
+}
+
+impl CustomPattern {
+    pub fn new(name: &str, regex: Regex, values: HashMap<String, String>) -> Self {
+        Self {
+            name: name.to_string(),
+            regex,
+            values: Arc::new(values),
+        }
+    }
+
+    // Load every pattern, names must be unique and differ from the built-in kinds
+    pub fn load_all(configs: &[CustomPatternConfig], app_support_path: &str) -> Result<Vec<Self>> {
+        let mut names = HashSet::new();
+        configs
+            .iter()
+            .map(|config| {
+                if !names.insert(config.name.as_str()) {
+                    return Err(NaliError::Config(format!(
+                        "Custom token {} is declared more than once",
+                        config.name
+                    )));
+                }
+                Self::load(config, app_support_path)
+            })
+            .collect()
+    }
+
+    // Compile the pattern and read its lookup file, if any
+    pub fn load(config: &CustomPatternConfig, app_support_path: &str) -> Result<Self> {
+        // `detect`, `annotate` and `overlap` would read the name as the built-in kind
+        if !matches!(TokenKind::from(config.name.clone()), TokenKind::Custom(_)) {
+            return Err(NaliError::Config(format!(
+                "Custom token {} has the name of a built-in kind",
+                config.name
+            )));
+        }
+        let regex = Regex::new(&config.pattern).map_err(|e| {
+            NaliError::Config(format!(
+                "Invalid pattern for custom token {}: {}",
+                config.name, e
+            ))
+        })?;
+        let values = match config.full_source_path(app_support_path) {
+            Some(path) => {
+                let contents = fs::read_to_string(&path).map_err(|e| {
+                    NaliError::Config(format!("Failed to read lookup file {}: {}", path, e))
+                })?;
+                Self::parse_values(&contents)
+            }
+            None => HashMap::new(),
+        };
+        Ok(Self::new(&config.name, regex, values))
+    }
+
+    // One `key value` pair per line, separated by whitespace, `#` starts a comment
+    fn parse_values(contents: &str) -> HashMap<String, String> {
+        contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .filter_map(|line| {
+                let (key, value) = line.split_once(char::is_whitespace)?;
+                Some((key.to_string(), value.trim().to_string()))
+            })
+            .collect()
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn regex(&self) -> &Regex {
+        &self.regex
+    }
+
+    pub fn lookup(&self, text: &str) -> Option<String> {
+        self.values.get(text).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(name: &str) -> CustomPatternConfig {
+        CustomPatternConfig {
+            name: name.to_string(),
+            pattern: "srv-[a-z0-9]{4}".to_string(),
+            source: None,
+        }
+    }
+
+    #[test]
+    fn rejects_built_in_kind_names() {
+        for name in ["ipv4", "ipv6", "domain"] {
+            let error = CustomPattern::load(&config(name), "").unwrap_err();
+            assert!(matches!(error, NaliError::Config(_)), "{}", name);
+        }
+        assert_eq!(
+            CustomPattern::load(&config("host"), "").unwrap().name(),
+            "host"
+        );
+    }
+
+    #[test]
+    fn rejects_duplicate_names() {
+        let error = CustomPattern::load_all(&[config("host"), config("host")], "").unwrap_err();
+        assert!(error.to_string().contains("more than once"), "{}", error);
+        let patterns = CustomPattern::load_all(&[config("host"), config("rack")], "").unwrap();
+        assert_eq!(patterns.len(), 2);
+    }
+
+    #[test]
+    fn parses_lookup_files() {
+        let values = CustomPattern::parse_values("# hosts\nsrv-a1b2  web frontend \n\nsrv-c3d4\n");
+        assert_eq!(values.len(), 1);
+        assert_eq!(values["srv-a1b2"], "web frontend");
+    }
+}
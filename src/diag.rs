@@ -0,0 +1,41 @@
+use std::{
+    fmt::Display,
+    sync::atomic::{AtomicU8, Ordering},
+};
+
+// How much nali reports on stderr, stdout only ever carries annotated text
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Verbosity {
+    // Errors only
+    Quiet,
+    // Errors and warnings
+    Normal,
+    // Everything, including informational messages
+    Verbose,
+}
+
+static VERBOSITY: AtomicU8 = AtomicU8::new(Verbosity::Normal as u8);
+
+pub fn set_verbosity(verbosity: Verbosity) {
+    VERBOSITY.store(verbosity as u8, Ordering::Relaxed);
+}
+
+fn enabled(verbosity: Verbosity) -> bool {
+    VERBOSITY.load(Ordering::Relaxed) >= verbosity as u8
+}
+
+pub fn error(message: impl Display) {
+    eprintln!("nali: {}", message);
+}
+
+pub fn warn(message: impl Display) {
+    if enabled(Verbosity::Normal) {
+        eprintln!("nali: warning: {}", message);
+    }
+}
+
+pub fn info(message: impl Display) {
+    if enabled(Verbosity::Verbose) {
+        eprintln!("nali: {}", message);
+    }
+}
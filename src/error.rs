@@ -0,0 +1,49 @@
+use std::{fmt::Display, io};
+
+pub type Result<T> = std::result::Result<T, NaliError>;
+
+#[derive(Debug)]
+pub enum NaliError {
+    // The config file or a setting derived from it is invalid
+    Config(String),
+    // A database could not be opened or read
+    Database(String),
+    // Reading the input or writing the output failed
+    Io(io::Error),
+}
+
+impl NaliError {
+    // Exit codes follow sysexits.h
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            NaliError::Config(_) => 78,   // EX_CONFIG
+            NaliError::Database(_) => 66, // EX_NOINPUT
+            NaliError::Io(_) => 74,       // EX_IOERR
+        }
+    }
+}
+
+impl Display for NaliError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NaliError::Config(message) => write!(f, "Config error: {}", message),
+            NaliError::Database(message) => write!(f, "Database error: {}", message),
+            NaliError::Io(e) => write!(f, "I/O error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for NaliError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            NaliError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for NaliError {
+    fn from(e: io::Error) -> Self {
+        NaliError::Io(e)
+    }
+}
@@ -1,8 +1,7 @@
-use std::cmp::Ordering;
-
 use crate::geo::geodb::GeoDB;
-use crate::parser::Parser;
-use crate::token::Token;
+use crate::overlap::Match;
+use crate::parser::{Parser, ParserOptions};
+use crate::token::TokenKind;
 use crate::NaliText;
 
 #[derive(PartialEq, Clone, Copy, Debug)]
@@ -37,21 +36,13 @@ struct IPv6Token {
 }
 
 #[derive(Default)]
-pub struct FastParser {}
+pub struct FastParser {
+    options: ParserOptions,
+}
 
 impl FastParser {
-    // Check if two ranges are overlapping
-    fn is_overlapping(start1: usize, end1: usize, start2: usize, end2: usize) -> bool {
-        start1 < end2 && start2 < end1
-    }
-
-    // Decide which token to keep when two tokens are overlapping
-    fn should_keep_first(token1: &Token, start1: usize, token2: &Token, start2: usize) -> bool {
-        match token1.priority().cmp(&token2.priority()) {
-            Ordering::Greater => true,           // First token has higher priority
-            Ordering::Less => false,             // Second token has higher priority
-            Ordering::Equal => start1 <= start2, // Same priority, keep the first one
-        }
+    pub fn new(options: ParserOptions) -> Self {
+        Self { options }
     }
 
     fn match_ipv6(&self, input: &str) -> Vec<(usize, usize)> {
@@ -74,11 +65,13 @@ impl FastParser {
             }
         }
 
-        let chars: Vec<char> = input.chars().collect();
+        // Addresses are pure ASCII, so scanning bytes keeps every match on a
+        // char boundary while reporting byte offsets
+        let chars = input.as_bytes();
         let mut i = 0;
 
         while i < chars.len() {
-            let c = chars[i];
+            let c = chars[i] as char;
 
             match (v6_token.last_state, is_hex_char(c)) {
                 (IPv6State::Any, IPv6State::Hex) => {
@@ -185,7 +178,7 @@ impl FastParser {
             // Find last non colon character
             let mut last_non_colon = chars.len();
             for i in (0..chars.len()).rev() {
-                if chars[i] != ':' {
+                if chars[i] != b':' {
                     last_non_colon = i + 1;
                     break;
                 }
@@ -206,11 +199,13 @@ impl FastParser {
             start: 0,
         };
 
-        let chars: Vec<char> = input.chars().collect();
+        // Addresses are pure ASCII, so scanning bytes keeps every match on a
+        // char boundary while reporting byte offsets
+        let chars = input.as_bytes();
         let mut i = 0;
 
         while i < chars.len() {
-            let c = chars[i];
+            let c = chars[i] as char;
 
             // IPv4 parsing
             match (v4_token.last_state, c) {
@@ -302,71 +297,22 @@ impl<G: GeoDB> Parser<G> for FastParser {
     }
 
     fn parse(&self, input: &str, db: &G) -> NaliText {
-        let mut tokens: Vec<Token> = Vec::new();
-        let mut matches: Vec<(usize, usize, Token)> = Vec::new();
-        let ipv4_matches = self.match_ipv4(input);
-        let ipv6_matches = self.match_ipv6(input);
-
-        ipv4_matches.iter().for_each(|(start, end)| {
-            let ip = &input[*start..*end];
-            matches.push((*start, *end, Token::IPv4(ip.to_string(), db.lookup(ip))));
-        });
-
-        ipv6_matches.iter().for_each(|(start, end)| {
-            let ip = &input[*start..*end];
-            matches.push((*start, *end, Token::IPv6(ip.to_string(), db.lookup(ip))));
-        });
-
-        // Sort matches by start position
-        matches.sort_by_key(|(start, _, _)| *start);
-
-        // Handle overlapping matches
-        let mut filtered_matches = Vec::new();
-        let mut i = 0;
-        while i < matches.len() {
-            let current = &matches[i];
-            let mut should_add = true;
-            let mut j = i + 1;
+        let mut matches: Vec<Match> = Vec::new();
 
-            while j < matches.len()
-                && Self::is_overlapping(current.0, current.1, matches[j].0, matches[j].1)
-            {
-                if !Self::should_keep_first(&current.2, current.0, &matches[j].2, matches[j].0) {
-                    should_add = false;
-                    break;
-                }
-                j += 1;
-            }
-
-            if should_add {
-                filtered_matches.push(current.clone());
-                while j < matches.len()
-                    && Self::is_overlapping(current.0, current.1, matches[j].0, matches[j].1)
-                {
-                    j += 1;
-                }
-                i = j;
-            } else {
-                i += 1;
-            }
+        if self.options.detects(&TokenKind::IPv4) {
+            self.match_ipv4(input).iter().for_each(|(start, end)| {
+                matches.push(Match::new(*start, *end, TokenKind::IPv4));
+            });
         }
 
-        let mut last_end = 0;
-
-        // Construct the final token sequence
-        for (start, end, token) in filtered_matches {
-            if start > last_end {
-                tokens.push(Token::Plain(input[last_end..start].to_string()));
-            }
-            tokens.push(token);
-            last_end = end;
+        if self.options.detects(&TokenKind::IPv6) {
+            self.match_ipv6(input).iter().for_each(|(start, end)| {
+                matches.push(Match::new(*start, *end, TokenKind::IPv6));
+            });
         }
 
-        // Add the remaining plain text
-        if last_end < input.len() {
-            tokens.push(Token::Plain(input[last_end..].to_string()));
-        }
+        self.options.match_custom(input, &mut matches);
 
-        NaliText::new(tokens)
+        self.options.build_text(input, matches, db)
     }
 }
@@ -0,0 +1,222 @@
+use std::{net::IpAddr, str::FromStr};
+
+use ipnetwork::IpNetwork;
+use regex::Regex;
+
+use crate::{
+    config::FilterRulesConfig,
+    error::{NaliError, Result},
+    token::TokenKind,
+};
+
+// An IP network in CIDR notation, a bare address is a single-host network
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cidr(IpNetwork);
+
+impl Cidr {
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        // Match IPv4-mapped IPv6 addresses against IPv4 networks
+        let ip = match (self.0, ip) {
+            (IpNetwork::V4(_), IpAddr::V6(v6)) => match v6.to_ipv4_mapped() {
+                Some(v4) => IpAddr::V4(v4),
+                None => return false,
+            },
+            _ => *ip,
+        };
+        self.0.contains(ip)
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        s.trim()
+            .parse()
+            .map(Cidr)
+            .map_err(|e| format!("Invalid CIDR {}: {}", s, e))
+    }
+}
+
+// A set of rules a token may match
+#[derive(Debug, Clone, Default)]
+pub struct FilterRules {
+    cidrs: Vec<Cidr>,
+    domains: Vec<String>,
+    regexes: Vec<Regex>,
+}
+
+impl FilterRules {
+    pub fn new(config: &FilterRulesConfig) -> Result<Self> {
+        let cidrs = config
+            .cidrs
+            .iter()
+            .map(|cidr| cidr.parse().map_err(NaliError::Config))
+            .collect::<Result<_>>()?;
+        let domains = config
+            .domains
+            .iter()
+            .map(|domain| domain.trim_matches('.').to_ascii_lowercase())
+            .collect();
+        let regexes = config
+            .regexes
+            .iter()
+            .map(|regex| {
+                Regex::new(regex)
+                    .map_err(|e| NaliError::Config(format!("Invalid filter regex: {}", e)))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            cidrs,
+            domains,
+            regexes,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cidrs.is_empty() && self.domains.is_empty() && self.regexes.is_empty()
+    }
+
+    // CIDRs apply to addresses, domains match themselves and their subdomains,
+    // regexes apply to the text of every kind
+    pub fn matches(&self, kind: &TokenKind, text: &str) -> bool {
+        let by_kind = match kind {
+            TokenKind::IPv4 | TokenKind::IPv6 => {
+                // Strip the zone index of link-local addresses
+                let addr = text.split('%').next().unwrap_or(text);
+                addr.parse::<IpAddr>()
+                    .map(|ip| self.cidrs.iter().any(|cidr| cidr.contains(&ip)))
+                    .unwrap_or(false)
+            }
+            TokenKind::Domain => {
+                let text = text.trim_end_matches('.').to_ascii_lowercase();
+                self.domains.iter().any(|domain| {
+                    text == *domain
+                        || text
+                            .strip_suffix(domain.as_str())
+                            .is_some_and(|sub| sub.ends_with('.'))
+                })
+            }
+            TokenKind::Custom(_) => false,
+        };
+        by_kind || self.regexes.iter().any(|regex| regex.is_match(text))
+    }
+}
+
+// Decides which tokens get annotated, tokens that don't are left as plain text
+#[derive(Debug, Clone, Default)]
+pub struct AnnotationFilter {
+    ignore: FilterRules,
+    allow: FilterRules,
+}
+
+impl AnnotationFilter {
+    pub fn new(ignore: FilterRules, allow: FilterRules) -> Self {
+        Self { ignore, allow }
+    }
+
+    // An empty allow list allows everything
+    pub fn should_annotate(&self, kind: &TokenKind, text: &str) -> bool {
+        (self.allow.is_empty() || self.allow.matches(kind, text))
+            && !self.ignore.matches(kind, text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use super::*;
+    use crate::{
+        geo::geodb::{GeoDB, GeoLocation},
+        token::Token,
+        Parser, ParserOptions, RegexParser,
+    };
+
+    fn rules(cidrs: &[&str], domains: &[&str]) -> FilterRules {
+        FilterRules::new(&FilterRulesConfig {
+            cidrs: cidrs.iter().map(|cidr| cidr.to_string()).collect(),
+            domains: domains.iter().map(|domain| domain.to_string()).collect(),
+            regexes: Vec::new(),
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn matches_addresses_inside_cidrs() {
+        let rules = rules(&["10.0.0.0/8", "192.168.1.1", "fd00::/8"], &[]);
+        assert!(rules.matches(&TokenKind::IPv4, "10.255.0.1"));
+        assert!(!rules.matches(&TokenKind::IPv4, "11.0.0.1"));
+        // A bare address is a single host
+        assert!(rules.matches(&TokenKind::IPv4, "192.168.1.1"));
+        assert!(!rules.matches(&TokenKind::IPv4, "192.168.1.2"));
+        assert!(rules.matches(&TokenKind::IPv6, "fd12:3456::1"));
+        assert!(!rules.matches(&TokenKind::IPv6, "fe80::1%eth0"));
+        // IPv4-mapped addresses match IPv4 networks
+        assert!(rules.matches(&TokenKind::IPv6, "::ffff:10.0.0.1"));
+        assert!(!rules.matches(&TokenKind::IPv6, "::ffff:11.0.0.1"));
+    }
+
+    #[test]
+    fn rejects_invalid_cidrs() {
+        let config = FilterRulesConfig {
+            cidrs: vec!["10.0.0.0/33".to_string()],
+            ..FilterRulesConfig::default()
+        };
+        assert!(matches!(
+            FilterRules::new(&config),
+            Err(NaliError::Config(_))
+        ));
+    }
+
+    #[test]
+    fn matches_domains_and_their_subdomains() {
+        let rules = rules(&[], &[".Example.com"]);
+        assert!(rules.matches(&TokenKind::Domain, "example.com"));
+        assert!(rules.matches(&TokenKind::Domain, "WWW.example.com."));
+        assert!(!rules.matches(&TokenKind::Domain, "badexample.com"));
+        assert!(!rules.matches(&TokenKind::Domain, "example.com.cn"));
+        // Domains only apply to domain tokens
+        assert!(!rules.matches(&TokenKind::IPv4, "example.com"));
+    }
+
+    // Records the addresses it is asked about
+    #[derive(Default)]
+    struct Recorder(RefCell<Vec<String>>);
+
+    impl GeoDB for Recorder {
+        fn lookup(&self, ip: &str) -> Option<GeoLocation> {
+            self.0.borrow_mut().push(ip.to_string());
+            None
+        }
+    }
+
+    #[test]
+    fn filtered_tokens_are_not_looked_up() {
+        let options = ParserOptions {
+            filter: AnnotationFilter::new(rules(&["10.0.0.0/8"], &[]), FilterRules::default()),
+            ..ParserOptions::default()
+        };
+        let db = Recorder::default();
+        let text = RegexParser::new(options).parse("10.1.2.3 and 1.1.1.1", &db);
+        assert_eq!(*db.0.borrow(), ["1.1.1.1"]);
+        assert_eq!(
+            text.tokens(),
+            [
+                Token::Plain("10.1.2.3 and ".to_string()),
+                Token::IPv4("1.1.1.1".to_string(), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn an_allow_list_annotates_only_its_matches() {
+        let filter = AnnotationFilter::new(FilterRules::default(), rules(&["1.1.1.0/24"], &[]));
+        assert!(filter.should_annotate(&TokenKind::IPv4, "1.1.1.1"));
+        assert!(!filter.should_annotate(&TokenKind::IPv4, "8.8.8.8"));
+        let filter = AnnotationFilter::new(rules(&["1.1.1.1"], &[]), rules(&["1.1.1.0/24"], &[]));
+        // Ignoring wins over allowing
+        assert!(!filter.should_annotate(&TokenKind::IPv4, "1.1.1.1"));
+        assert!(filter.should_annotate(&TokenKind::IPv4, "1.1.1.2"));
+    }
+}
@@ -0,0 +1,55 @@
+use std::net::IpAddr;
+
+use super::geodb::{DbMetadata, GeoDB, GeoLocation};
+use crate::config::CompositeStrategy;
+
+pub struct CompositeBackend {
+    pub db: Box<dyn GeoDB>,
+    // Only IPv4 (4) or IPv6 (6) addresses are looked up, every address when None
+    pub ip_version: Option<u8>,
+}
+
+impl CompositeBackend {
+    fn answers(&self, ip: Option<IpAddr>) -> bool {
+        !matches!(
+            (self.ip_version, ip),
+            (Some(4), Some(IpAddr::V6(_))) | (Some(6), Some(IpAddr::V4(_)))
+        )
+    }
+}
+
+// Asks several databases in order, e.g. a City database and then an ISP database
+pub struct Composite {
+    strategy: CompositeStrategy,
+    backends: Vec<CompositeBackend>,
+}
+
+impl Composite {
+    pub fn new(strategy: CompositeStrategy, backends: Vec<CompositeBackend>) -> Self {
+        Self { strategy, backends }
+    }
+}
+
+impl GeoDB for Composite {
+    fn lookup(&self, ip: &str) -> Option<GeoLocation> {
+        // Backends get the text as it is, the version is only needed for routing
+        let addr = ip.split('%').next().and_then(|addr| addr.parse().ok());
+        let mut backends = self.backends.iter().filter(|backend| backend.answers(addr));
+        match self.strategy {
+            CompositeStrategy::FirstHit => backends.find_map(|backend| backend.db.lookup(ip)),
+            CompositeStrategy::Merge => backends
+                .filter_map(|backend| backend.db.lookup(ip))
+                .reduce(|mut geo_location, other| {
+                    geo_location.merge(other);
+                    geo_location
+                }),
+        }
+    }
+
+    // The first backend that describes itself, usually the main database
+    fn metadata(&self) -> Option<DbMetadata> {
+        self.backends
+            .iter()
+            .find_map(|backend| backend.db.metadata())
+    }
+}
@@ -17,7 +17,8 @@ impl Default for FakeGeo {
 impl GeoDB for FakeGeo {
     fn lookup(&self, _ip: &str) -> Option<GeoLocation> {
         Some(GeoLocation {
-            location: "Fake Location".to_string(),
+            country: Some("Fake Location".to_string()),
+            ..GeoLocation::default()
         })
     }
 }
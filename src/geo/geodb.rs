@@ -1,13 +1,265 @@
-#[derive(Debug, Clone, PartialEq)]
+use std::{
+    fmt,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+// Everything a database knows about an address, each database fills what it has
+#[derive(Debug, Clone, Default, PartialEq)]
 pub struct GeoLocation {
-    pub location: String,
+    pub continent: Option<String>,
+    pub country: Option<String>,
+    // ISO 3166-1 alpha-2
+    pub country_code: Option<String>,
+    // From the largest to the smallest, e.g. a state and then a county
+    pub subdivisions: Vec<String>,
+    pub city: Option<String>,
+    pub postal: Option<String>,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    // In kilometers
+    pub accuracy_radius: Option<u16>,
+    pub time_zone: Option<String>,
+    // Where the network is registered, e.g. by the ISP, which is not always where it is used
+    pub registered_country: Option<String>,
+    pub registered_country_code: Option<String>,
+    // The country the users represent, e.g. the owner of a military base abroad
+    pub represented_country: Option<String>,
+    pub represented_country_code: Option<String>,
+    // How the users represent it, e.g. `military`
+    pub represented_type: Option<String>,
+    // The network is announced from many places, the location is only one of them
+    pub is_anycast: bool,
+    // The autonomous system the address belongs to and the organization running it
+    pub asn: Option<u32>,
+    pub organization: Option<String>,
+    pub isp: Option<String>,
+    // e.g. `Cable/DSL`, `Cellular` or `Corporate`
+    pub connection_type: Option<String>,
+    // Who uses the network, e.g. `hosting`, `mobile ISP` or `education`
+    pub usage_type: Option<String>,
+    // The second level domain of the address, e.g. `google.com`
+    pub domain: Option<String>,
+    // How the address hides its users, e.g. `VPN` or `Tor exit node`
+    pub anonymizers: Vec<String>,
+}
+
+impl GeoLocation {
+    pub fn is_empty(&self) -> bool {
+        *self == GeoLocation::default()
+    }
+
+    // Fill the fields this location does not know yet from `other`
+    pub fn merge(&mut self, other: GeoLocation) {
+        fn fill<T>(field: &mut Option<T>, other: Option<T>) {
+            if field.is_none() {
+                *field = other;
+            }
+        }
+        fill(&mut self.continent, other.continent);
+        fill(&mut self.country, other.country);
+        fill(&mut self.country_code, other.country_code);
+        if self.subdivisions.is_empty() {
+            self.subdivisions = other.subdivisions;
+        }
+        fill(&mut self.city, other.city);
+        fill(&mut self.postal, other.postal);
+        fill(&mut self.latitude, other.latitude);
+        fill(&mut self.longitude, other.longitude);
+        fill(&mut self.accuracy_radius, other.accuracy_radius);
+        fill(&mut self.time_zone, other.time_zone);
+        fill(&mut self.registered_country, other.registered_country);
+        fill(
+            &mut self.registered_country_code,
+            other.registered_country_code,
+        );
+        fill(&mut self.represented_country, other.represented_country);
+        fill(
+            &mut self.represented_country_code,
+            other.represented_country_code,
+        );
+        fill(&mut self.represented_type, other.represented_type);
+        self.is_anycast |= other.is_anycast;
+        fill(&mut self.asn, other.asn);
+        fill(&mut self.organization, other.organization);
+        fill(&mut self.isp, other.isp);
+        fill(&mut self.connection_type, other.connection_type);
+        fill(&mut self.usage_type, other.usage_type);
+        fill(&mut self.domain, other.domain);
+        for anonymizer in other.anonymizers {
+            if !self.anonymizers.contains(&anonymizer) {
+                self.anonymizers.push(anonymizer);
+            }
+        }
+    }
+
+    // Reasons the country may be misleading, e.g. `VPN`, `anycast` or `registered in United States`
+    pub fn notes(&self) -> Vec<String> {
+        let mut notes = self.anonymizers.clone();
+        if self.is_anycast {
+            notes.push("anycast".to_string());
+        }
+        if let Some(represented) = &self.represented_country {
+            notes.push(match &self.represented_type {
+                Some(represented_type) => format!("{} of {}", represented_type, represented),
+                None => format!("represents {}", represented),
+            });
+        }
+        let registered_elsewhere = match (&self.registered_country_code, &self.country_code) {
+            (Some(registered), Some(country)) => registered != country,
+            (Some(_), None) => true,
+            _ => false,
+        };
+        if let (true, Some(registered)) = (registered_elsewhere, &self.registered_country) {
+            notes.push(format!("registered in {}", registered));
+        }
+        notes
+    }
+}
+
+// `{country}, {subdivision}, {city} | {isp} ({notes})`. Databases that know nothing about the
+// place are described by what they do know: the ISP, `AS{asn} {organization}`, the
+// domain, the connection type or the usage type
+impl fmt::Display for GeoLocation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let place = [
+            self.country.as_deref(),
+            self.subdivisions.first().map(String::as_str),
+            self.city.as_deref(),
+        ]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+        let description = if !place.is_empty() {
+            Some(match &self.isp {
+                Some(isp) => format!("{} | {}", place.join(", "), isp),
+                None => place.join(", "),
+            })
+        } else {
+            match (&self.isp, self.asn, &self.organization) {
+                (Some(isp), ..) => Some(isp.clone()),
+                (None, Some(asn), Some(organization)) => {
+                    Some(format!("AS{} {}", asn, organization))
+                }
+                (None, Some(asn), None) => Some(format!("AS{}", asn)),
+                (None, None, organization) => organization
+                    .clone()
+                    .or_else(|| self.domain.clone())
+                    .or_else(|| self.connection_type.clone())
+                    .or_else(|| self.usage_type.clone()),
+            }
+        };
+        let notes = self.notes().join(", ");
+        match (description, notes.is_empty()) {
+            (Some(description), true) => write!(f, "{}", description),
+            (Some(description), false) => write!(f, "{} ({})", description, notes),
+            (None, _) => write!(f, "{}", notes),
+        }
+    }
+}
+
+pub const SECONDS_PER_DAY: u64 = 86400;
+
+// Seconds from 1970-01-01 to a civil date, from Howard Hinnant's date algorithms
+pub fn epoch_from_civil(year: i64, month: u32, day: u32) -> Option<u64> {
+    let year = if month <= 2 {
+        year.checked_sub(1)?
+    } else {
+        year
+    };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = (month as i64 + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era.checked_mul(146097)?.checked_add(doe - 719468)?;
+    u64::try_from(days).ok()?.checked_mul(SECONDS_PER_DAY)
+}
+
+// The civil date of a Unix timestamp, the inverse of `epoch_from_civil`
+pub fn civil_from_epoch(epoch: u64) -> (i64, u32, u32) {
+    let days = (epoch / SECONDS_PER_DAY) as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let doe = days.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
 }
+
+// What a database file says about itself
+#[derive(Debug, Clone, PartialEq)]
+pub struct DbMetadata {
+    pub database_type: String,
+    // Seconds since the Unix epoch
+    pub build_epoch: u64,
+    pub ip_version: u16,
+    pub node_count: u32,
+    pub languages: Vec<String>,
+}
+
+impl DbMetadata {
+    // Time since the database was built, zero for build dates in the future
+    pub fn age(&self) -> Duration {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        now.saturating_sub(Duration::from_secs(self.build_epoch))
+    }
+}
+
 pub trait GeoDB {
     fn lookup(&self, ip: &str) -> Option<GeoLocation>;
+
+    // Databases without a file format to describe return None
+    fn metadata(&self) -> Option<DbMetadata> {
+        None
+    }
 }
 
 impl GeoDB for Box<dyn GeoDB> {
     fn lookup(&self, ip: &str) -> Option<GeoLocation> {
         (**self).lookup(ip)
     }
+
+    fn metadata(&self) -> Option<DbMetadata> {
+        (**self).metadata()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display_separates_the_place_from_the_isp() {
+        let mut location = GeoLocation {
+            country: Some("中国".to_string()),
+            subdivisions: vec!["浙江省".to_string()],
+            city: Some("杭州市".to_string()),
+            isp: Some("阿里云".to_string()),
+            ..GeoLocation::default()
+        };
+        assert_eq!(location.to_string(), "中国, 浙江省, 杭州市 | 阿里云");
+        location.is_anycast = true;
+        assert_eq!(
+            location.to_string(),
+            "中国, 浙江省, 杭州市 | 阿里云 (anycast)"
+        );
+
+        // Without a place the ISP is the whole description
+        let isp_only = GeoLocation {
+            isp: Some("阿里云".to_string()),
+            ..GeoLocation::default()
+        };
+        assert_eq!(isp_only.to_string(), "阿里云");
+        let asn_only = GeoLocation {
+            asn: Some(13335),
+            organization: Some("Cloudflare".to_string()),
+            ..GeoLocation::default()
+        };
+        assert_eq!(asn_only.to_string(), "AS13335 Cloudflare");
+    }
 }
@@ -1,18 +1,69 @@
-use std::collections::BTreeMap;
+use std::{collections::BTreeMap, net::IpAddr};
 
 use maxminddb::geoip2;
 use sys_locale::get_locale;
 
-use super::geodb::{GeoDB, GeoLocation};
+use super::{
+    geodb::{DbMetadata, GeoDB, GeoLocation},
+    geolite2_asn::GeoLite2ASN,
+};
+use crate::{
+    diag,
+    error::{NaliError, Result},
+};
+
+// The record layout of a MaxMind database, told apart by `metadata.database_type`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RecordKind {
+    City,
+    Country,
+    Asn,
+    Isp,
+    ConnectionType,
+    AnonymousIp,
+    Domain,
+}
+
+impl RecordKind {
+    // e.g. `GeoLite2-City`, `GeoIP2-Enterprise`, `GeoIP2-Anonymous-IP` or `DBIP-ASN-Lite`
+    pub fn detect(database_type: &str) -> Option<Self> {
+        let kinds = [
+            ("Anonymous-IP", RecordKind::AnonymousIp),
+            ("Connection-Type", RecordKind::ConnectionType),
+            ("Domain", RecordKind::Domain),
+            ("ISP", RecordKind::Isp),
+            ("ASN", RecordKind::Asn),
+            ("Country", RecordKind::Country),
+            ("City", RecordKind::City),
+            ("Enterprise", RecordKind::City),
+        ];
+        kinds
+            .into_iter()
+            .find(|(name, _)| database_type.contains(name))
+            .map(|(_, kind)| kind)
+    }
+}
 
 pub struct GeoLite2 {
     reader: maxminddb::Reader<Vec<u8>>,
+    kind: RecordKind,
     language: String,
+    // Fills the ASN fields the City database does not have
+    asn: Option<Box<GeoLite2ASN>>,
 }
 
 impl GeoLite2 {
-    pub fn new(mmdb_path: &str) -> Self {
-        let reader = maxminddb::Reader::open_readfile(mmdb_path).unwrap();
+    pub fn new(mmdb_path: &str) -> Result<Self> {
+        let reader = maxminddb::Reader::open_readfile(mmdb_path)
+            .map_err(|e| NaliError::Database(format!("Failed to open {}: {}", mmdb_path, e)))?;
+        let database_type = &reader.metadata.database_type;
+        let kind = RecordKind::detect(database_type).unwrap_or_else(|| {
+            diag::warn(format!(
+                "Unknown database type {} in {}, reading it as a City database",
+                database_type, mmdb_path
+            ));
+            RecordKind::City
+        });
         let l_code = if let Some(locale) = get_locale() {
             match locale.as_str() {
                 l if l.starts_with("de") => "de",
@@ -28,10 +79,21 @@ impl GeoLite2 {
         } else {
             "en" // Default to English if locale is unavailable
         };
-        Self {
+        Ok(Self {
             reader,
+            kind,
             language: String::from(l_code),
-        }
+            asn: None,
+        })
+    }
+
+    pub fn kind(&self) -> RecordKind {
+        self.kind
+    }
+
+    pub fn with_asn(mut self, asn: GeoLite2ASN) -> Self {
+        self.asn = Some(Box::new(asn));
+        self
     }
 
     fn get_localized_name(names: &Option<BTreeMap<&str, &str>>, language: &str) -> Option<String> {
@@ -42,35 +104,173 @@ impl GeoLite2 {
                 .or_else(|| names.get("en").map(|s| s.to_string()))
         })
     }
+
+    // Everything the record of `ip` holds, empty when the database has no record for it
+    pub fn decode(&self, ip: IpAddr) -> GeoLocation {
+        let decoded = match self.kind {
+            RecordKind::City => self
+                .reader
+                .lookup::<geoip2::City>(ip)
+                .map(|city| self.city_location(city)),
+            // The same record without the city level fields
+            RecordKind::Country => self.reader.lookup::<geoip2::Country>(ip).map(|country| {
+                self.city_location(geoip2::City {
+                    city: None,
+                    continent: country.continent,
+                    country: country.country,
+                    location: None,
+                    postal: None,
+                    registered_country: country.registered_country,
+                    represented_country: country.represented_country,
+                    subdivisions: None,
+                    traits: country.traits,
+                })
+            }),
+            RecordKind::Asn => self
+                .reader
+                .lookup::<geoip2::Asn>(ip)
+                .map(|asn| GeoLocation {
+                    asn: asn.autonomous_system_number,
+                    organization: asn.autonomous_system_organization.map(str::to_string),
+                    ..GeoLocation::default()
+                }),
+            RecordKind::Isp => self
+                .reader
+                .lookup::<geoip2::Isp>(ip)
+                .map(|isp| GeoLocation {
+                    asn: isp.autonomous_system_number,
+                    organization: isp.autonomous_system_organization.map(str::to_string),
+                    isp: isp.isp.map(str::to_string),
+                    ..GeoLocation::default()
+                }),
+            RecordKind::ConnectionType => {
+                self.reader
+                    .lookup::<geoip2::ConnectionType>(ip)
+                    .map(|connection_type| GeoLocation {
+                        connection_type: connection_type.connection_type.map(str::to_string),
+                        ..GeoLocation::default()
+                    })
+            }
+            RecordKind::AnonymousIp => {
+                self.reader
+                    .lookup::<geoip2::AnonymousIp>(ip)
+                    .map(|anonymous_ip| GeoLocation {
+                        anonymizers: [
+                            (anonymous_ip.is_anonymous_vpn, "VPN"),
+                            (anonymous_ip.is_tor_exit_node, "Tor exit node"),
+                            (anonymous_ip.is_public_proxy, "public proxy"),
+                            (anonymous_ip.is_residential_proxy, "residential proxy"),
+                            (anonymous_ip.is_hosting_provider, "hosting provider"),
+                        ]
+                        .into_iter()
+                        .filter(|(flag, _)| *flag == Some(true))
+                        .map(|(_, name)| name.to_string())
+                        .collect(),
+                        ..GeoLocation::default()
+                    })
+            }
+            RecordKind::Domain => {
+                self.reader
+                    .lookup::<geoip2::Domain>(ip)
+                    .map(|domain| GeoLocation {
+                        domain: domain.domain.map(str::to_string),
+                        ..GeoLocation::default()
+                    })
+            }
+        };
+        decoded.unwrap_or_default()
+    }
+
+    fn city_location(&self, city_data: geoip2::City) -> GeoLocation {
+        let language = self.language.as_str();
+        let location = city_data.location.as_ref();
+        let traits = city_data.traits.as_ref();
+        GeoLocation {
+            continent: city_data
+                .continent
+                .and_then(|continent| Self::get_localized_name(&continent.names, language)),
+            country: city_data
+                .country
+                .as_ref()
+                .and_then(|country| Self::get_localized_name(&country.names, language)),
+            country_code: city_data
+                .country
+                .and_then(|country| country.iso_code)
+                .map(str::to_string),
+            subdivisions: city_data
+                .subdivisions
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(|subdivision| Self::get_localized_name(&subdivision.names, language))
+                .collect(),
+            city: city_data
+                .city
+                .and_then(|city| Self::get_localized_name(&city.names, language)),
+            postal: city_data
+                .postal
+                .and_then(|postal| postal.code)
+                .map(str::to_string),
+            latitude: location.and_then(|location| location.latitude),
+            longitude: location.and_then(|location| location.longitude),
+            accuracy_radius: location.and_then(|location| location.accuracy_radius),
+            time_zone: location
+                .and_then(|location| location.time_zone)
+                .map(str::to_string),
+            registered_country: city_data
+                .registered_country
+                .as_ref()
+                .and_then(|country| Self::get_localized_name(&country.names, language)),
+            registered_country_code: city_data
+                .registered_country
+                .and_then(|country| country.iso_code)
+                .map(str::to_string),
+            represented_country: city_data
+                .represented_country
+                .as_ref()
+                .and_then(|country| Self::get_localized_name(&country.names, language)),
+            represented_country_code: city_data
+                .represented_country
+                .as_ref()
+                .and_then(|country| country.iso_code)
+                .map(str::to_string),
+            represented_type: city_data
+                .represented_country
+                .and_then(|country| country.representation_type)
+                .map(str::to_string),
+            is_anycast: traits.and_then(|traits| traits.is_anycast) == Some(true),
+            anonymizers: match traits.and_then(|traits| traits.is_anonymous_proxy) {
+                Some(true) => vec!["anonymous proxy".to_string()],
+                _ => Vec::new(),
+            },
+            ..GeoLocation::default()
+        }
+    }
+}
+
+impl From<&maxminddb::Metadata> for DbMetadata {
+    fn from(metadata: &maxminddb::Metadata) -> Self {
+        DbMetadata {
+            database_type: metadata.database_type.clone(),
+            build_epoch: metadata.build_epoch,
+            ip_version: metadata.ip_version,
+            node_count: metadata.node_count,
+            languages: metadata.languages.clone(),
+        }
+    }
 }
 
 impl GeoDB for GeoLite2 {
     fn lookup(&self, ip: &str) -> Option<GeoLocation> {
-        let ip = ip.parse().unwrap();
-        let city_data: geoip2::City = self.reader.lookup(ip).unwrap_or(geoip2::City {
-            city: None,
-            continent: None,
-            country: None,
-            location: None,
-            postal: None,
-            registered_country: None,
-            represented_country: None,
-            subdivisions: None,
-            traits: None,
-        });
-        let city_name =
-            Self::get_localized_name(&city_data.city.and_then(|city| city.names), &self.language);
-        let country_name = Self::get_localized_name(
-            &city_data.country.and_then(|country| country.names),
-            &self.language,
-        );
-        match (city_name, country_name) {
-            (Some(city), Some(country)) => Some(GeoLocation {
-                location: format!("{} {}", country, city),
-            }),
-            (Some(city), None) => Some(GeoLocation { location: city }),
-            (None, Some(country)) => Some(GeoLocation { location: country }),
-            (None, None) => None,
+        // Drop the zone index of link-local addresses, it is not part of the address
+        let ip = ip.split('%').next()?.parse().ok()?;
+        let mut geo_location = self.decode(ip);
+        if let Some(asn) = &self.asn {
+            geo_location.merge(asn.decode(ip));
         }
+        (!geo_location.is_empty()).then_some(geo_location)
+    }
+
+    fn metadata(&self) -> Option<DbMetadata> {
+        Some(DbMetadata::from(&self.reader.metadata))
     }
 }
@@ -0,0 +1,39 @@
+use std::net::IpAddr;
+
+use super::{
+    geodb::{DbMetadata, GeoDB, GeoLocation},
+    geolite2::{GeoLite2, RecordKind},
+};
+use crate::error::{NaliError, Result};
+
+// The autonomous system of an address, from GeoLite2-ASN or the ASN part of GeoIP2-ISP
+pub struct GeoLite2ASN {
+    geolite2: GeoLite2,
+}
+
+impl GeoLite2ASN {
+    pub fn new(mmdb_path: &str) -> Result<Self> {
+        let geolite2 = GeoLite2::new(mmdb_path)?;
+        if !matches!(geolite2.kind(), RecordKind::Asn | RecordKind::Isp) {
+            return Err(NaliError::Database(format!(
+                "{} is not an ASN or ISP database",
+                mmdb_path
+            )));
+        }
+        Ok(Self { geolite2 })
+    }
+
+    pub fn decode(&self, ip: IpAddr) -> GeoLocation {
+        self.geolite2.decode(ip)
+    }
+}
+
+impl GeoDB for GeoLite2ASN {
+    fn lookup(&self, ip: &str) -> Option<GeoLocation> {
+        self.geolite2.lookup(ip)
+    }
+
+    fn metadata(&self) -> Option<DbMetadata> {
+        self.geolite2.metadata()
+    }
+}
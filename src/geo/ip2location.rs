@@ -0,0 +1,694 @@
+use std::{fs, net::IpAddr};
+
+use super::geodb::{epoch_from_civil, DbMetadata, GeoDB, GeoLocation};
+use crate::error::{NaliError, Result};
+
+const HEADER_SIZE: usize = 64;
+// One `(first row, last row)` pair per leading 16 bits of the address
+const INDEX_ENTRY_SIZE: usize = 8;
+
+// Column positions by database type (DB1–DB26), 0 when the type has no such column.
+// Position 1 is the start address of the row
+const COUNTRY: [u8; 27] = [
+    0, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2,
+];
+const REGION: [u8; 27] = [
+    0, 0, 0, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3,
+];
+const CITY: [u8; 27] = [
+    0, 0, 0, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4,
+];
+const ISP: [u8; 27] = [
+    0, 0, 3, 0, 5, 0, 7, 5, 7, 0, 8, 0, 9, 0, 9, 0, 9, 0, 9, 7, 9, 0, 9, 7, 9, 9, 9,
+];
+const LATITUDE: [u8; 27] = [
+    0, 0, 0, 0, 0, 5, 5, 0, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5,
+];
+const LONGITUDE: [u8; 27] = [
+    0, 0, 0, 0, 0, 6, 6, 0, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6,
+];
+const DOMAIN: [u8; 27] = [
+    0, 0, 0, 0, 0, 0, 0, 6, 8, 0, 9, 0, 10, 0, 10, 0, 10, 0, 10, 8, 10, 0, 10, 8, 10, 10, 10,
+];
+const ZIP_CODE: [u8; 27] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 7, 7, 7, 7, 0, 7, 7, 7, 0, 7, 0, 7, 7, 7, 0, 7, 7, 7,
+];
+const TIME_ZONE: [u8; 27] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 8, 8, 7, 8, 8, 8, 7, 8, 0, 8, 8, 8, 0, 8, 8, 8,
+];
+const USAGE_TYPE: [u8; 27] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 12, 20, 20, 20,
+];
+const ASN: [u8; 27] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 24,
+];
+const AS_NAME: [u8; 27] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 25,
+];
+
+// The positions of the columns nali reads, 0 when missing
+#[derive(Debug, Clone, Copy, Default)]
+struct Columns {
+    country: u8,
+    region: u8,
+    city: u8,
+    latitude: u8,
+    longitude: u8,
+    zip_code: u8,
+    time_zone: u8,
+    isp: u8,
+    domain: u8,
+    usage_type: u8,
+    asn: u8,
+    as_name: u8,
+}
+
+impl Columns {
+    fn new(db_type: usize) -> Option<Self> {
+        Some(Columns {
+            country: *COUNTRY.get(db_type)?,
+            region: REGION[db_type],
+            city: CITY[db_type],
+            latitude: LATITUDE[db_type],
+            longitude: LONGITUDE[db_type],
+            zip_code: ZIP_CODE[db_type],
+            time_zone: TIME_ZONE[db_type],
+            isp: ISP[db_type],
+            domain: DOMAIN[db_type],
+            usage_type: USAGE_TYPE[db_type],
+            asn: ASN[db_type],
+            as_name: AS_NAME[db_type],
+        })
+    }
+
+    fn max(&self) -> u8 {
+        [
+            self.country,
+            self.region,
+            self.city,
+            self.latitude,
+            self.longitude,
+            self.zip_code,
+            self.time_zone,
+            self.isp,
+            self.domain,
+            self.usage_type,
+            self.asn,
+            self.as_name,
+        ]
+        .into_iter()
+        .max()
+        .unwrap_or(0)
+    }
+}
+
+// The rows of one address family
+#[derive(Debug, Clone, Copy)]
+struct Table {
+    count: usize,
+    base: usize,
+    // Offset of the index by leading 16 bits, older files have none
+    index: Option<usize>,
+    // Bytes of the start address of a row, 4 for IPv4 and 16 for IPv6
+    ip_size: usize,
+    row_size: usize,
+}
+
+// An IP2Location `.BIN` file (DB1–DB26). Rows are
+// sorted by start address, a row ends where the next one starts
+pub struct IP2Location {
+    data: Vec<u8>,
+    db_type: usize,
+    columns: Columns,
+    ipv4: Table,
+    ipv6: Table,
+    // The release date, years are counted from 2000
+    date: (i64, u32, u32),
+}
+
+impl IP2Location {
+    pub fn new(path: &str) -> Result<Self> {
+        let data = fs::read(path)
+            .map_err(|e| NaliError::Database(format!("Failed to open {}: {}", path, e)))?;
+        let invalid = || NaliError::Database(format!("{} is not an IP2Location BIN file", path));
+        if data.len() < HEADER_SIZE {
+            return Err(invalid());
+        }
+        let db_type = data[0] as usize;
+        let db_column = data[1] as usize;
+        // Files from before 2021 have no product code, other products such as
+        // IP2Proxy (2) lay out their columns differently
+        if data[29] > 1 {
+            return Err(invalid());
+        }
+        let columns = Columns::new(db_type)
+            .filter(|_| db_type > 0)
+            .ok_or_else(|| {
+                NaliError::Database(format!("Unsupported database type {} in {}", db_type, path))
+            })?;
+        if db_column < 2 || (columns.max() as usize) > db_column {
+            return Err(invalid());
+        }
+        // Offsets in the file are counted from 1, 0 means missing
+        let offset = |at: usize| {
+            read_u32(&data, at)
+                .map(|offset| (offset as usize).checked_sub(1))
+                .ok_or_else(invalid)
+        };
+        let table = |count_at: usize, base_at: usize, index_at: usize, ip_size: usize| {
+            Ok::<_, NaliError>(Table {
+                count: read_u32(&data, count_at).ok_or_else(invalid)? as usize,
+                base: offset(base_at)?.unwrap_or(0),
+                index: offset(index_at)?,
+                ip_size,
+                row_size: ip_size + (db_column - 1) * 4,
+            })
+        };
+        let ipv4 = table(5, 9, 21, 4)?;
+        let ipv6 = table(13, 17, 25, 16)?;
+        if ipv4.count == 0 && ipv6.count == 0 {
+            return Err(invalid());
+        }
+        let date = (2000 + data[2] as i64, data[3] as u32, data[4] as u32);
+        Ok(Self {
+            data,
+            db_type,
+            columns,
+            ipv4,
+            ipv6,
+            date,
+        })
+    }
+
+    fn read_ip(&self, table: &Table, row: usize) -> Option<u128> {
+        let offset = table.base + row * table.row_size;
+        let bytes = self.data.get(offset..offset + table.ip_size)?;
+        Some(
+            bytes
+                .iter()
+                .rev()
+                .fold(0, |value, byte| value << 8 | *byte as u128),
+        )
+    }
+
+    // The offset of the row containing `ip`
+    fn find(&self, table: &Table, ip: u128) -> Option<usize> {
+        if table.count == 0 {
+            return None;
+        }
+        let bits = table.ip_size as u32 * 8;
+        // The last address is where the last row ends
+        let ip = ip.min((u128::MAX >> (128 - bits)) - 1);
+        let (mut low, mut high) = match table.index {
+            Some(index) => {
+                let offset = index + (ip >> (bits - 16)) as usize * INDEX_ENTRY_SIZE;
+                (
+                    read_u32(&self.data, offset)? as usize,
+                    read_u32(&self.data, offset + 4)? as usize,
+                )
+            }
+            None => (0, table.count),
+        };
+        while low <= high {
+            let mid = (low + high) / 2;
+            let start = self.read_ip(table, mid)?;
+            if ip < start {
+                high = mid.checked_sub(1)?;
+            } else if ip >= self.read_ip(table, mid + 1)? {
+                low = mid + 1;
+            } else {
+                return Some(table.base + mid * table.row_size);
+            }
+        }
+        None
+    }
+
+    fn column(&self, table: &Table, row: usize, position: u8) -> Option<u32> {
+        if position < 2 {
+            return None;
+        }
+        read_u32(
+            &self.data,
+            row + table.ip_size + (position as usize - 2) * 4,
+        )
+    }
+
+    // A string column points to a length prefixed string
+    fn string(&self, table: &Table, row: usize, position: u8) -> Option<String> {
+        self.string_at(self.column(table, row, position)? as usize)
+    }
+
+    fn string_at(&self, offset: usize) -> Option<String> {
+        let len = *self.data.get(offset)? as usize;
+        let bytes = self.data.get(offset + 1..offset + 1 + len)?;
+        let text = String::from_utf8_lossy(bytes);
+        let text = text.trim();
+        // `-` marks an unknown value
+        (!text.is_empty() && text != "-").then(|| text.to_string())
+    }
+
+    fn float(&self, table: &Table, row: usize, position: u8) -> Option<f64> {
+        let value = f32::from_bits(self.column(table, row, position)?);
+        // Through the shortest decimal form, `37.40599` rather than `37.40599060058594`
+        value
+            .is_finite()
+            .then(|| value.to_string().parse().ok())
+            .flatten()
+    }
+
+    fn decode(&self, table: &Table, row: usize) -> GeoLocation {
+        let columns = &self.columns;
+        // The country code, followed 3 bytes later by the name
+        let country = self
+            .column(table, row, columns.country)
+            .map(|offset| offset as usize);
+        let country_code = country.and_then(|offset| self.string_at(offset));
+        let country = country.and_then(|offset| self.string_at(offset + 3));
+        // Both coordinates are 0 when unknown
+        let (latitude, longitude) = match (
+            self.float(table, row, columns.latitude),
+            self.float(table, row, columns.longitude),
+        ) {
+            (Some(latitude), Some(longitude)) if latitude != 0.0 || longitude != 0.0 => {
+                (Some(latitude), Some(longitude))
+            }
+            _ => (None, None),
+        };
+        GeoLocation {
+            country,
+            country_code,
+            subdivisions: self
+                .string(table, row, columns.region)
+                .into_iter()
+                .collect(),
+            city: self.string(table, row, columns.city),
+            postal: self.string(table, row, columns.zip_code),
+            latitude,
+            longitude,
+            // A UTC offset, e.g. `+08:00`
+            time_zone: self.string(table, row, columns.time_zone),
+            asn: self
+                .string(table, row, columns.asn)
+                .and_then(|asn| asn.parse().ok()),
+            organization: self.string(table, row, columns.as_name),
+            isp: self.string(table, row, columns.isp),
+            domain: self.string(table, row, columns.domain),
+            usage_type: self
+                .string(table, row, columns.usage_type)
+                .map(|usage_type| usage_name(&usage_type)),
+            ..GeoLocation::default()
+        }
+    }
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        data.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+// Usage types are codes, e.g. `DCH` or `ISP/MOB` for networks with several uses
+fn usage_name(usage_type: &str) -> String {
+    usage_type
+        .split('/')
+        .map(|code| match code {
+            "COM" => "commercial",
+            "ORG" => "organization",
+            "GOV" => "government",
+            "MIL" => "military",
+            "EDU" => "education",
+            "LIB" => "library",
+            "CDN" => "CDN",
+            "ISP" => "ISP",
+            "MOB" => "mobile ISP",
+            "DCH" => "hosting",
+            "SES" => "search engine spider",
+            "RSV" => "reserved",
+            code => code,
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+impl GeoDB for IP2Location {
+    fn lookup(&self, ip: &str) -> Option<GeoLocation> {
+        let (table, ip) = match ip.split('%').next()?.parse().ok()? {
+            IpAddr::V4(ip) => (&self.ipv4, u32::from(ip) as u128),
+            IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+                Some(ip) => (&self.ipv4, u32::from(ip) as u128),
+                None => (&self.ipv6, u128::from(ip)),
+            },
+        };
+        let geo_location = self.decode(table, self.find(table, ip)?);
+        (!geo_location.is_empty()).then_some(geo_location)
+    }
+
+    fn metadata(&self) -> Option<DbMetadata> {
+        let (year, month, day) = self.date;
+        Some(DbMetadata {
+            database_type: format!("IP2Location DB{}", self.db_type),
+            build_epoch: epoch_from_civil(year, month, day)?,
+            ip_version: if self.ipv6.count > 0 { 6 } else { 4 },
+            node_count: u32::try_from(self.ipv4.count + self.ipv6.count).unwrap_or(u32::MAX),
+            languages: vec!["en".to_string()],
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geo::temp_file;
+
+    // Every column nali reads, written only where the database type has it
+    #[derive(Clone, Copy)]
+    struct Row {
+        start: &'static str,
+        country: (&'static str, &'static str),
+        region: &'static str,
+        city: &'static str,
+        latitude: f32,
+        longitude: f32,
+        zip_code: &'static str,
+        time_zone: &'static str,
+        isp: &'static str,
+        domain: &'static str,
+        usage_type: &'static str,
+        asn: &'static str,
+        as_name: &'static str,
+    }
+
+    const UNKNOWN: Row = Row {
+        start: "0.0.0.0",
+        country: ("-", "-"),
+        region: "-",
+        city: "-",
+        latitude: 0.0,
+        longitude: 0.0,
+        zip_code: "-",
+        time_zone: "-",
+        isp: "-",
+        domain: "-",
+        usage_type: "-",
+        asn: "-",
+        as_name: "-",
+    };
+    const BRISBANE: Row = Row {
+        start: "1.1.1.0",
+        country: ("AU", "Australia"),
+        region: "Queensland",
+        city: "Brisbane",
+        latitude: -27.46794,
+        longitude: 153.02809,
+        zip_code: "4000",
+        time_zone: "+10:00",
+        isp: "APNIC and Cloudflare DNS Resolver project",
+        domain: "cloudflare.com",
+        usage_type: "CDN",
+        asn: "13335",
+        as_name: "CLOUDFLARENET",
+    };
+    const HANGZHOU: Row = Row {
+        start: "223.5.5.0",
+        country: ("CN", "China"),
+        region: "Zhejiang",
+        city: "Hangzhou",
+        latitude: 30.29365,
+        longitude: 120.16142,
+        zip_code: "310000",
+        time_zone: "+08:00",
+        isp: "Hangzhou Alibaba Advertising Co Ltd.",
+        domain: "aliyun.com",
+        usage_type: "ISP/MOB",
+        asn: "37963",
+        as_name: "Hangzhou Alibaba Advertising Co.,Ltd.",
+    };
+
+    fn at(start: &'static str, row: Row) -> Row {
+        Row { start, ..row }
+    }
+
+    fn ipv4_rows() -> Vec<Row> {
+        vec![
+            UNKNOWN,
+            BRISBANE,
+            at("1.1.2.0", UNKNOWN),
+            // Known place, unknown coordinates
+            at(
+                "8.8.8.0",
+                Row {
+                    country: ("US", "United States of America"),
+                    latitude: 0.0,
+                    longitude: 0.0,
+                    ..BRISBANE
+                },
+            ),
+            at("8.8.9.0", UNKNOWN),
+            HANGZHOU,
+        ]
+    }
+
+    // Starts below the IPv4-mapped range, so a mapped address found here
+    // would come back as `ZZ`
+    fn ipv6_rows() -> Vec<Row> {
+        vec![
+            at(
+                "::",
+                Row {
+                    country: ("ZZ", "Reserved"),
+                    ..UNKNOWN
+                },
+            ),
+            at("2001:4860::", BRISBANE),
+            at("2001:4861::", UNKNOWN),
+            at("2400:3200::", HANGZHOU),
+        ]
+    }
+
+    // A `.BIN` file of `db_type` holding the given rows, each table closed by
+    // a row at its last address like in released files
+    fn bin(db_type: usize, ipv4: &[Row], ipv6: &[Row], with_index: bool) -> Vec<u8> {
+        let columns = Columns::new(db_type).unwrap();
+        let db_column = columns.max() as usize;
+        let mut tables = [ipv4.to_vec(), ipv6.to_vec()];
+        tables[0].push(at("255.255.255.255", UNKNOWN));
+        if !ipv6.is_empty() {
+            tables[1].push(at("ffff:ffff:ffff:ffff:ffff:ffff:ffff:ffff", UNKNOWN));
+        }
+        let start = |row: &Row| match row.start.parse().unwrap() {
+            IpAddr::V4(ip) => u32::from(ip) as u128,
+            IpAddr::V6(ip) => u128::from(ip),
+        };
+
+        let index_size = 65536 * INDEX_ENTRY_SIZE;
+        let indexes = match (with_index, ipv6.is_empty()) {
+            (false, _) => 0,
+            (true, true) => 1,
+            (true, false) => 2,
+        };
+        let ipv4_base = HEADER_SIZE + indexes * index_size;
+        let ipv6_base = ipv4_base + tables[0].len() * db_column * 4;
+        let strings_base = ipv6_base + tables[1].len() * (16 + (db_column - 1) * 4);
+
+        let mut data = vec![0; ipv4_base];
+        let mut strings = Vec::new();
+        // A length prefixed string taking at least `width` bytes
+        let mut string = |text: &str, width: usize| {
+            let start = strings.len();
+            strings.push(text.len() as u8);
+            strings.extend_from_slice(text.as_bytes());
+            strings.resize(strings.len().max(start + width), 0);
+            (strings_base + start) as u32
+        };
+        for (table, ip_size) in tables.iter().zip([4, 16]) {
+            for row in table {
+                data.extend_from_slice(&start(row).to_le_bytes()[..ip_size]);
+                for position in 2..=db_column as u8 {
+                    let value = match position {
+                        p if p == columns.country => {
+                            // The code takes 3 bytes, the name follows it
+                            let offset = string(row.country.0, 3);
+                            string(row.country.1, 0);
+                            offset
+                        }
+                        p if p == columns.latitude => row.latitude.to_bits(),
+                        p if p == columns.longitude => row.longitude.to_bits(),
+                        p if p == columns.region => string(row.region, 0),
+                        p if p == columns.city => string(row.city, 0),
+                        p if p == columns.zip_code => string(row.zip_code, 0),
+                        p if p == columns.time_zone => string(row.time_zone, 0),
+                        p if p == columns.isp => string(row.isp, 0),
+                        p if p == columns.domain => string(row.domain, 0),
+                        p if p == columns.usage_type => string(row.usage_type, 0),
+                        p if p == columns.asn => string(row.asn, 0),
+                        p if p == columns.as_name => string(row.as_name, 0),
+                        // Columns nali does not read
+                        _ => string("-", 0),
+                    };
+                    data.extend_from_slice(&value.to_le_bytes());
+                }
+            }
+        }
+        data.extend_from_slice(&strings);
+
+        // The first and last row starting under each leading 16 bits
+        for (i, (table, bits)) in tables.iter().zip([32, 128]).take(indexes).enumerate() {
+            let starts: Vec<u128> = table.iter().map(start).collect();
+            let last_at = |ip: u128| starts.iter().rposition(|start| *start <= ip).unwrap_or(0);
+            for prefix in 0..65536u128 {
+                let low = prefix << (bits - 16);
+                let high = low | (u128::MAX >> (128 - bits + 16));
+                let rows = [last_at(low), last_at(high).min(table.len() - 2)];
+                let offset = HEADER_SIZE + i * index_size + prefix as usize * INDEX_ENTRY_SIZE;
+                for (j, row) in rows.into_iter().enumerate() {
+                    data[offset + 4 * j..offset + 4 * j + 4]
+                        .copy_from_slice(&(row as u32).to_le_bytes());
+                }
+            }
+        }
+
+        // Offsets in the header count from 1
+        let from_one = |offset: usize, present: bool| if present { offset + 1 } else { 0 };
+        let header = [
+            (5, tables[0].len() - 1),
+            (9, ipv4_base + 1),
+            (13, tables[1].len().saturating_sub(1)),
+            (17, from_one(ipv6_base, !ipv6.is_empty())),
+            (21, from_one(HEADER_SIZE, with_index)),
+            (25, from_one(HEADER_SIZE + index_size, indexes == 2)),
+        ];
+        data[..5].copy_from_slice(&[db_type as u8, db_column as u8, 26, 9, 1]);
+        for (at, value) in header {
+            data[at..at + 4].copy_from_slice(&(value as u32).to_le_bytes());
+        }
+        data[29] = 1;
+        data
+    }
+
+    fn open(name: &str, data: &[u8]) -> IP2Location {
+        IP2Location::new(&temp_file(name, data)).unwrap()
+    }
+
+    #[test]
+    fn fills_only_the_columns_of_each_type() {
+        let db = |db_type| {
+            let data = bin(db_type, &ipv4_rows(), &ipv6_rows(), true);
+            open(&format!("bin-db{}", db_type), &data)
+                .lookup("1.1.1.1")
+                .unwrap()
+        };
+
+        let db1 = db(1);
+        assert_eq!(db1.country_code.as_deref(), Some("AU"));
+        assert_eq!(db1.country.as_deref(), Some("Australia"));
+        assert_eq!(db1.city, None);
+
+        // DB2 adds the ISP but no place below the country
+        let db2 = db(2);
+        assert!(db2.subdivisions.is_empty());
+        assert_eq!(db2.isp.as_deref(), Some(BRISBANE.isp));
+
+        let db3 = db(3);
+        assert_eq!(db3.subdivisions, vec!["Queensland".to_string()]);
+        assert_eq!(db3.city.as_deref(), Some("Brisbane"));
+        assert_eq!((db3.latitude, db3.isp), (None, None));
+
+        let db5 = db(5);
+        assert_eq!(db5.latitude, Some(-27.46794));
+        assert_eq!(db5.longitude, Some(153.02809));
+        assert_eq!(db5.postal, None);
+
+        let db11 = db(11);
+        assert_eq!(db11.postal.as_deref(), Some("4000"));
+        assert_eq!(db11.time_zone.as_deref(), Some("+10:00"));
+        assert_eq!(db11.isp, None);
+        assert_eq!(db11.usage_type, None);
+
+        let db26 = db(26);
+        assert_eq!(db26.domain.as_deref(), Some("cloudflare.com"));
+        assert_eq!(db26.usage_type.as_deref(), Some("CDN"));
+        assert_eq!(db26.asn, Some(13335));
+        assert_eq!(db26.organization.as_deref(), Some("CLOUDFLARENET"));
+    }
+
+    #[test]
+    fn drops_unknown_coordinates() {
+        let db = open("bin-coordinates", &bin(5, &ipv4_rows(), &[], true));
+        let google = db.lookup("8.8.8.8").unwrap();
+        assert_eq!(google.country_code.as_deref(), Some("US"));
+        assert_eq!((google.latitude, google.longitude), (None, None));
+    }
+
+    #[test]
+    fn answers_ipv4_mapped_addresses_from_the_ipv4_rows() {
+        let db = open("bin-mapped", &bin(3, &ipv4_rows(), &ipv6_rows(), true));
+        assert_eq!(db.lookup("::ffff:1.1.1.1"), db.lookup("1.1.1.1"));
+        assert_eq!(db.lookup("::ffff:1.1.2.1"), None);
+        assert_eq!(
+            db.lookup("::1").unwrap().country_code.as_deref(),
+            Some("ZZ")
+        );
+        assert_eq!(
+            db.lookup("2001:4860::1").unwrap().city.as_deref(),
+            Some("Brisbane")
+        );
+    }
+
+    #[test]
+    fn searches_the_same_without_an_index() {
+        let indexed = open("bin-indexed", &bin(3, &ipv4_rows(), &ipv6_rows(), true));
+        let scanned = open("bin-scanned", &bin(3, &ipv4_rows(), &ipv6_rows(), false));
+        for ip in [
+            "0.0.0.0",
+            "1.1.1.0",
+            "1.1.1.255",
+            "8.8.8.8",
+            "223.5.5.0",
+            // The closing row is not a range of its own
+            "255.255.255.255",
+            "::",
+            "2001:4860:ffff::",
+            "2400:3200::1",
+            "ffff:ffff:ffff:ffff:ffff:ffff:ffff:ffff",
+        ] {
+            assert_eq!(indexed.lookup(ip), scanned.lookup(ip), "{}", ip);
+        }
+        assert_eq!(
+            scanned.lookup("255.255.255.255").unwrap().city.as_deref(),
+            Some("Hangzhou")
+        );
+    }
+
+    #[test]
+    fn rejects_headers_it_cannot_read() {
+        let data = bin(3, &ipv4_rows(), &[], true);
+        let error = |name: &str, change: &dyn Fn(&mut Vec<u8>)| {
+            let mut data = data.clone();
+            change(&mut data);
+            IP2Location::new(&temp_file(name, &data))
+                .err()
+                .unwrap()
+                .to_string()
+        };
+        // IP2Proxy files share the format but not the columns
+        assert!(error("bin-proxy", &|data| data[29] = 2).contains("not an IP2Location"));
+        assert!(error("bin-type-0", &|data| data[0] = 0).contains("Unsupported database type 0"));
+        assert!(error("bin-type-27", &|data| data[0] = 27).contains("Unsupported database type 27"));
+        // DB3 rows have 4 columns, DB5 needs 6
+        assert!(error("bin-columns", &|data| data[0] = 5).contains("not an IP2Location"));
+        assert!(error("bin-short", &|data| data.truncate(HEADER_SIZE - 1))
+            .contains("not an IP2Location"));
+    }
+
+    #[test]
+    fn names_the_type_and_release() {
+        let metadata = open("bin-metadata", &bin(11, &ipv4_rows(), &ipv6_rows(), true))
+            .metadata()
+            .unwrap();
+        assert_eq!(metadata.database_type, "IP2Location DB11");
+        // 2026-09-01T00:00:00Z
+        assert_eq!(metadata.build_epoch, 1_788_220_800);
+        assert_eq!(metadata.ip_version, 6);
+        assert_eq!(metadata.node_count, 10);
+    }
+}
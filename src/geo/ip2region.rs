@@ -0,0 +1,350 @@
+use std::{
+    borrow::Cow,
+    fs::{self, File},
+    io::{Read, Seek, SeekFrom},
+    net::IpAddr,
+    sync::Mutex,
+};
+
+use super::geodb::{DbMetadata, GeoDB, GeoLocation};
+use crate::{
+    config::Ip2RegionMode,
+    error::{NaliError, Result},
+};
+
+const HEADER_SIZE: usize = 256;
+// One `(first segment, end of the last segment)` pointer pair per leading two bytes
+const VECTOR_INDEX_COLS: usize = 256;
+const VECTOR_INDEX_ENTRY_SIZE: usize = 8;
+const VECTOR_INDEX_SIZE: usize = VECTOR_INDEX_COLS * VECTOR_INDEX_COLS * VECTOR_INDEX_ENTRY_SIZE;
+// Start and end address, data length and data pointer
+const SEGMENT_SIZE: usize = 14;
+
+enum Source {
+    // Shared by every lookup, reads seek first
+    File(Mutex<File>),
+    Memory(Vec<u8>),
+}
+
+impl Source {
+    fn read(&self, offset: usize, len: usize) -> Option<Cow<'_, [u8]>> {
+        match self {
+            Source::File(file) => {
+                let mut file = file.lock().ok()?;
+                let mut buf = vec![0; len];
+                file.seek(SeekFrom::Start(offset as u64)).ok()?;
+                file.read_exact(&mut buf).ok()?;
+                Some(Cow::Owned(buf))
+            }
+            Source::Memory(data) => data.get(offset..offset + len).map(Cow::Borrowed),
+        }
+    }
+}
+
+// An ip2region `.xdb` file (format version 2, IPv4), regions are
+// `country|region|province|city|isp` with `0` for unknown parts
+pub struct Ip2Region {
+    source: Source,
+    // Cached in `vector_index` mode, `memory` mode reads it from the loaded file
+    vector_index: Option<Vec<u8>>,
+    created_at: u64,
+    segment_count: usize,
+}
+
+impl Ip2Region {
+    pub fn new(path: &str, mode: Ip2RegionMode) -> Result<Self> {
+        let open_error =
+            |e: std::io::Error| NaliError::Database(format!("Failed to open {}: {}", path, e));
+        let source = match mode {
+            Ip2RegionMode::Memory => Source::Memory(fs::read(path).map_err(open_error)?),
+            Ip2RegionMode::File | Ip2RegionMode::VectorIndex => {
+                Source::File(Mutex::new(File::open(path).map_err(open_error)?))
+            }
+        };
+        let invalid = || NaliError::Database(format!("{} is not an ip2region xdb file", path));
+        let header = source.read(0, HEADER_SIZE).ok_or_else(invalid)?;
+        let version = u16::from_le_bytes([header[0], header[1]]);
+        if version != 2 {
+            return Err(NaliError::Database(format!(
+                "Unsupported xdb version {} in {}",
+                version, path
+            )));
+        }
+        let created_at = read_u32(&header, 4).ok_or_else(invalid)? as u64;
+        let start_index = read_u32(&header, 8).ok_or_else(invalid)? as usize;
+        let end_index = read_u32(&header, 12).ok_or_else(invalid)? as usize;
+        if end_index < start_index {
+            return Err(invalid());
+        }
+        let vector_index = match mode {
+            Ip2RegionMode::VectorIndex => Some(
+                source
+                    .read(HEADER_SIZE, VECTOR_INDEX_SIZE)
+                    .ok_or_else(invalid)?
+                    .into_owned(),
+            ),
+            Ip2RegionMode::File | Ip2RegionMode::Memory => None,
+        };
+        drop(header);
+        Ok(Self {
+            source,
+            vector_index,
+            created_at,
+            segment_count: (end_index - start_index) / SEGMENT_SIZE + 1,
+        })
+    }
+
+    // The pointers of the segments whose start address begins with `ip`'s leading two bytes
+    fn segment_range(&self, ip: u32) -> Option<(usize, usize)> {
+        let row = (ip >> 24) as usize;
+        let col = (ip >> 16 & 0xff) as usize;
+        let offset = (row * VECTOR_INDEX_COLS + col) * VECTOR_INDEX_ENTRY_SIZE;
+        let entry = match &self.vector_index {
+            Some(vector_index) => {
+                Cow::Borrowed(vector_index.get(offset..offset + VECTOR_INDEX_ENTRY_SIZE)?)
+            }
+            None => self
+                .source
+                .read(HEADER_SIZE + offset, VECTOR_INDEX_ENTRY_SIZE)?,
+        };
+        Some((read_u32(&entry, 0)? as usize, read_u32(&entry, 4)? as usize))
+    }
+
+    // The region string of the segment containing `ip`
+    fn search(&self, ip: u32) -> Option<String> {
+        let (first, end) = self.segment_range(ip)?;
+        if end <= first {
+            return None;
+        }
+        let (mut low, mut high) = (0, (end - first) / SEGMENT_SIZE);
+        while low <= high {
+            let mid = (low + high) / 2;
+            let segment = self.source.read(first + mid * SEGMENT_SIZE, SEGMENT_SIZE)?;
+            if ip < read_u32(&segment, 0)? {
+                high = mid.checked_sub(1)?;
+            } else if ip > read_u32(&segment, 4)? {
+                low = mid + 1;
+            } else {
+                let len = u16::from_le_bytes([segment[8], segment[9]]) as usize;
+                let region = self.source.read(read_u32(&segment, 10)? as usize, len)?;
+                return Some(String::from_utf8_lossy(&region).into_owned());
+            }
+        }
+        None
+    }
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        data.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+// `0` marks an unknown part
+fn part(text: &str) -> Option<String> {
+    let text = text.trim();
+    (!text.is_empty() && text != "0").then(|| text.to_string())
+}
+
+impl GeoDB for Ip2Region {
+    fn lookup(&self, ip: &str) -> Option<GeoLocation> {
+        let ip = match ip.parse().ok()? {
+            IpAddr::V4(ip) => ip,
+            // The database only covers IPv4
+            IpAddr::V6(ip) => ip.to_ipv4_mapped()?,
+        };
+        let region = self.search(u32::from(ip))?;
+        let parts: Vec<&str> = region.split('|').collect();
+        // The older 5 part layout has a region, e.g. `华东`, between country and province
+        let (country, province, city, isp) = match parts[..] {
+            [country, _region, province, city, isp] => (country, province, city, isp),
+            [country, province, city, isp] => (country, province, city, isp),
+            _ => return None,
+        };
+        let geo_location = GeoLocation {
+            country: part(country),
+            subdivisions: part(province).into_iter().collect(),
+            city: part(city),
+            isp: part(isp),
+            ..GeoLocation::default()
+        };
+        (!geo_location.is_empty()).then_some(geo_location)
+    }
+
+    fn metadata(&self) -> Option<DbMetadata> {
+        Some(DbMetadata {
+            database_type: "ip2region".to_string(),
+            build_epoch: self.created_at,
+            ip_version: 4,
+            node_count: self.segment_count as u32,
+            languages: vec!["zh-CN".to_string()],
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+    use crate::geo::temp_file;
+
+    const MODES: [Ip2RegionMode; 3] = [
+        Ip2RegionMode::File,
+        Ip2RegionMode::VectorIndex,
+        Ip2RegionMode::Memory,
+    ];
+
+    // An xdb file with its region strings before the segments, so the last
+    // segment ends the file. Segments are sorted and never cross a vector slot,
+    // like the ones the official maker writes
+    fn xdb(segments: &[(&str, &str, &str)]) -> Vec<u8> {
+        let mut data = vec![0; HEADER_SIZE + VECTOR_INDEX_SIZE];
+        data[..2].copy_from_slice(&2u16.to_le_bytes());
+        data[4..8].copy_from_slice(&1_620_604_800u32.to_le_bytes());
+        let mut regions = Vec::new();
+        for (_, _, region) in segments {
+            regions.push((data.len(), region.len()));
+            data.extend_from_slice(region.as_bytes());
+        }
+        let first = data.len();
+        for ((start, end, _), (region_ptr, region_len)) in segments.iter().zip(regions) {
+            let start = u32::from(start.parse::<Ipv4Addr>().unwrap());
+            let end = u32::from(end.parse::<Ipv4Addr>().unwrap());
+            let ptr = data.len() as u32;
+            data.extend_from_slice(&start.to_le_bytes());
+            data.extend_from_slice(&end.to_le_bytes());
+            data.extend_from_slice(&(region_len as u16).to_le_bytes());
+            data.extend_from_slice(&(region_ptr as u32).to_le_bytes());
+            // Slots point at their first segment and just past their last one
+            let slot = HEADER_SIZE + (start >> 16) as usize * VECTOR_INDEX_ENTRY_SIZE;
+            if data[slot..slot + 4] == [0; 4] {
+                data[slot..slot + 4].copy_from_slice(&ptr.to_le_bytes());
+            }
+            let end_ptr = ptr + SEGMENT_SIZE as u32;
+            data[slot + 4..slot + 8].copy_from_slice(&end_ptr.to_le_bytes());
+        }
+        let last = data.len() - SEGMENT_SIZE;
+        data[8..12].copy_from_slice(&(first as u32).to_le_bytes());
+        data[12..16].copy_from_slice(&(last as u32).to_le_bytes());
+        data
+    }
+
+    const SEGMENTS: &[(&str, &str, &str)] = &[
+        ("1.0.0.0", "1.0.0.255", "澳大利亚|0|0|0|0"),
+        ("1.0.1.0", "1.0.3.255", "中国|华东|福建省|福州市|电信"),
+        ("1.0.8.0", "1.0.15.255", "中国|广东省|广州市|电信"),
+        ("8.8.0.0", "8.8.255.255", "美国|0|0|0|Level3"),
+        ("223.255.255.0", "223.255.255.255", "中国|0|0|0|0"),
+    ];
+
+    fn isp(db: &Ip2Region, ip: &str) -> Option<String> {
+        db.lookup(ip).and_then(|geo_location| geo_location.isp)
+    }
+
+    #[test]
+    fn every_mode_gives_the_same_answers() {
+        let path = temp_file("xdb-modes", &xdb(SEGMENTS));
+        let dbs: Vec<Ip2Region> = MODES
+            .into_iter()
+            .map(|mode| Ip2Region::new(&path, mode).unwrap())
+            .collect();
+        for ip in [
+            "1.0.0.0",
+            "1.0.2.1",
+            "1.0.5.1",
+            "1.0.15.255",
+            "8.8.8.8",
+            "9.9.9.9",
+            "223.255.255.255",
+            "::ffff:8.8.8.8",
+        ] {
+            let expected = dbs[0].lookup(ip);
+            for db in &dbs[1..] {
+                assert_eq!(db.lookup(ip), expected, "{}", ip);
+            }
+        }
+        for db in &dbs {
+            let metadata = db.metadata().unwrap();
+            assert_eq!(metadata.build_epoch, 1_620_604_800);
+            assert_eq!(metadata.node_count, 5);
+        }
+    }
+
+    #[test]
+    fn searches_slots_holding_one_segment() {
+        let path = temp_file("xdb-one-segment", &xdb(SEGMENTS));
+        for mode in MODES {
+            let db = Ip2Region::new(&path, mode).unwrap();
+            assert_eq!(isp(&db, "8.8.0.0").as_deref(), Some("Level3"));
+            assert_eq!(isp(&db, "8.8.255.255").as_deref(), Some("Level3"));
+            // The neighbouring slots are empty
+            assert_eq!(db.lookup("8.7.255.255"), None);
+            assert_eq!(db.lookup("8.9.0.0"), None);
+        }
+    }
+
+    #[test]
+    fn misses_past_the_last_segment_of_a_slot() {
+        let path = temp_file("xdb-slot-end", &xdb(SEGMENTS));
+        for mode in MODES {
+            let db = Ip2Region::new(&path, mode).unwrap();
+            // Between two segments, and after the last one of the slot
+            assert_eq!(db.lookup("1.0.4.0"), None, "{:?}", mode);
+            assert_eq!(db.lookup("1.0.16.0"), None, "{:?}", mode);
+            // The search looks one segment past the slot, here the end of the file
+            let db =
+                Ip2Region::new(&temp_file("xdb-file-end", &xdb(&SEGMENTS[..1])), mode).unwrap();
+            assert_eq!(db.lookup("1.0.1.0"), None, "{:?}", mode);
+        }
+    }
+
+    #[test]
+    fn reads_the_vector_index_as_needed() {
+        // The index is cut short after the slots of `1.x.x.x`
+        let mut data = xdb(SEGMENTS);
+        data.truncate(HEADER_SIZE + 2 * 256 * VECTOR_INDEX_ENTRY_SIZE);
+        let path = temp_file("xdb-short-index", &data);
+        // Only the vector index mode loads the whole index up front
+        assert!(Ip2Region::new(&path, Ip2RegionMode::VectorIndex).is_err());
+        for mode in [Ip2RegionMode::File, Ip2RegionMode::Memory] {
+            let db = Ip2Region::new(&path, mode).unwrap();
+            assert_eq!(db.lookup("1.0.0.1"), None, "{:?}", mode);
+            assert_eq!(db.lookup("8.8.8.8"), None, "{:?}", mode);
+        }
+    }
+
+    #[test]
+    fn rejects_other_format_versions() {
+        let mut data = xdb(SEGMENTS);
+        data[..2].copy_from_slice(&1u16.to_le_bytes());
+        let path = temp_file("xdb-version", &data);
+        for mode in MODES {
+            let error = Ip2Region::new(&path, mode).err().unwrap();
+            assert!(error.to_string().contains("version 1"), "{}", error);
+        }
+    }
+
+    #[test]
+    fn maps_both_region_layouts() {
+        let db = Ip2Region::new(
+            &temp_file("xdb-layouts", &xdb(SEGMENTS)),
+            Ip2RegionMode::Memory,
+        )
+        .unwrap();
+        // `country|region|province|city|isp`, the region is not kept
+        let fuzhou = db.lookup("1.0.2.1").unwrap();
+        assert_eq!(fuzhou.subdivisions, vec!["福建省".to_string()]);
+        assert_eq!(fuzhou.city.as_deref(), Some("福州市"));
+        // `country|province|city|isp`
+        let guangzhou = db.lookup("1.0.9.1").unwrap();
+        assert_eq!(guangzhou.subdivisions, vec!["广东省".to_string()]);
+        assert_eq!(guangzhou.city.as_deref(), Some("广州市"));
+        assert_eq!(guangzhou.isp.as_deref(), Some("电信"));
+        // `0` parts are unknown
+        let australia = db.lookup("1.0.0.1").unwrap();
+        assert_eq!(australia.country.as_deref(), Some("澳大利亚"));
+        assert!(australia.subdivisions.is_empty());
+        assert_eq!((australia.city, australia.isp), (None, None));
+    }
+}
@@ -1,3 +1,18 @@
+pub mod composite;
 pub mod fakegeo;
 pub mod geodb;
 pub mod geolite2;
+pub mod geolite2_asn;
+pub mod ip2location;
+pub mod ip2region;
+pub mod qqwry;
+mod wry;
+pub mod zxipv6wry;
+
+// Write a database file built by a unit test, returning its path
+#[cfg(test)]
+pub(crate) fn temp_file(name: &str, data: &[u8]) -> String {
+    let path = std::env::temp_dir().join(format!("nali-test-{}-{}", std::process::id(), name));
+    std::fs::write(&path, data).unwrap();
+    path.to_string_lossy().into_owned()
+}
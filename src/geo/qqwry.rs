@@ -0,0 +1,293 @@
+use std::{fs, net::IpAddr};
+
+use encoding_rs::GBK;
+
+use super::{
+    geodb::{epoch_from_civil, DbMetadata, GeoDB, GeoLocation},
+    wry::{self, field, read_u24},
+};
+use crate::error::{NaliError, Result};
+
+// Index entries are a 4 byte start address and a 3 byte record offset
+const INDEX_ENTRY_SIZE: usize = 7;
+
+// The cz88.net QQWry IPv4 database (`qqwry.dat`)
+pub struct QQWry {
+    data: Vec<u8>,
+    first_index: usize,
+    last_index: usize,
+}
+
+impl QQWry {
+    pub fn new(path: &str) -> Result<Self> {
+        let data = fs::read(path)
+            .map_err(|e| NaliError::Database(format!("Failed to open {}: {}", path, e)))?;
+        let invalid = || NaliError::Database(format!("{} is not a QQWry database", path));
+        if data.len() < 8 {
+            return Err(invalid());
+        }
+        let first_index = read_u32(&data, 0).ok_or_else(invalid)? as usize;
+        let last_index = read_u32(&data, 4).ok_or_else(invalid)? as usize;
+        if first_index > last_index
+            || (last_index - first_index) % INDEX_ENTRY_SIZE != 0
+            || last_index + INDEX_ENTRY_SIZE > data.len()
+        {
+            return Err(invalid());
+        }
+        Ok(Self {
+            data,
+            first_index,
+            last_index,
+        })
+    }
+
+    fn index_len(&self) -> usize {
+        (self.last_index - self.first_index) / INDEX_ENTRY_SIZE + 1
+    }
+
+    // The start address and record offset of an index entry
+    fn index_entry(&self, i: usize) -> Option<(u32, usize)> {
+        let offset = self.first_index + i * INDEX_ENTRY_SIZE;
+        Some((
+            read_u32(&self.data, offset)?,
+            read_u24(&self.data, offset + 4)?,
+        ))
+    }
+
+    // The record offset of the range containing `ip`
+    fn find(&self, ip: u32) -> Option<usize> {
+        // The last entry starting at or before `ip`
+        let (mut low, mut high) = (0, self.index_len());
+        while high - low > 1 {
+            let mid = (low + high) / 2;
+            if self.index_entry(mid)?.0 <= ip {
+                low = mid;
+            } else {
+                high = mid;
+            }
+        }
+        let (start, record) = self.index_entry(low)?;
+        let end = read_u32(&self.data, record)?;
+        (start <= ip && ip <= end).then_some(record)
+    }
+
+    // The country and area of the record at `record`, after its end address
+    fn read_record(&self, record: usize) -> Option<(String, String)> {
+        wry::read_record(&self.data, record + 4, |bytes| {
+            GBK.decode(bytes).0.into_owned()
+        })
+    }
+
+    // The last record names the release, e.g. `纯真网络` / `2024年10月16日IP数据`
+    fn release_date(&self) -> Option<(i64, u32, u32)> {
+        let (_, record) = self.index_entry(self.index_len() - 1)?;
+        let (_, area) = self.read_record(record)?;
+        let (year, rest) = area.split_once('年')?;
+        let (month, rest) = rest.split_once('月')?;
+        let (day, _) = rest.split_once('日')?;
+        Some((year.parse().ok()?, month.parse().ok()?, day.parse().ok()?))
+    }
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        data.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+impl GeoDB for QQWry {
+    fn lookup(&self, ip: &str) -> Option<GeoLocation> {
+        let ip = match ip.parse().ok()? {
+            IpAddr::V4(ip) => ip,
+            // The database only covers IPv4
+            IpAddr::V6(ip) => ip.to_ipv4_mapped()?,
+        };
+        let (country, area) = self.read_record(self.find(u32::from(ip))?)?;
+        let geo_location = GeoLocation {
+            country: field(country),
+            // Mostly the ISP, sometimes the organization using the range
+            isp: field(area),
+            ..GeoLocation::default()
+        };
+        (!geo_location.is_empty()).then_some(geo_location)
+    }
+
+    // Only known when the release record names a date
+    fn metadata(&self) -> Option<DbMetadata> {
+        let (year, month, day) = self.release_date()?;
+        let build_epoch = epoch_from_civil(year, month, day)?;
+        Some(DbMetadata {
+            database_type: "QQWry".to_string(),
+            build_epoch,
+            ip_version: 4,
+            node_count: self.index_len() as u32,
+            languages: vec!["zh-CN".to_string()],
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+    use crate::geo::temp_file;
+
+    fn gbk(text: &str) -> Vec<u8> {
+        let mut bytes = GBK.encode(text).0.into_owned();
+        bytes.push(0);
+        bytes
+    }
+
+    fn ip(text: &str) -> u32 {
+        u32::from(text.parse::<Ipv4Addr>().unwrap())
+    }
+
+    // A file with the given `(start, end, strings)` ranges, sorted by start
+    fn build(ranges: &[(&str, &str, Vec<u8>)]) -> Vec<u8> {
+        let mut data = vec![0; 8];
+        let mut index = Vec::new();
+        for (start, end, strings) in ranges {
+            index.extend_from_slice(&ip(start).to_le_bytes());
+            index.extend_from_slice(&(data.len() as u32).to_le_bytes()[..3]);
+            data.extend_from_slice(&ip(end).to_le_bytes());
+            data.extend_from_slice(strings);
+        }
+        let first_index = data.len() as u32;
+        let last_index = first_index + (index.len() - INDEX_ENTRY_SIZE) as u32;
+        data[..4].copy_from_slice(&first_index.to_le_bytes());
+        data[4..8].copy_from_slice(&last_index.to_le_bytes());
+        data.extend_from_slice(&index);
+        data
+    }
+
+    fn open(name: &str, data: &[u8]) -> QQWry {
+        QQWry::new(&temp_file(name, data)).unwrap()
+    }
+
+    fn sample() -> Vec<u8> {
+        build(&[
+            (
+                "1.0.0.0",
+                "1.0.0.255",
+                [gbk("北京市"), gbk("联通")].concat(),
+            ),
+            (
+                "2.0.0.0",
+                "2.0.0.255",
+                [gbk("上海市"), gbk(" CZ88.NET")].concat(),
+            ),
+            (
+                "255.255.255.0",
+                "255.255.255.255",
+                [gbk("纯真网络"), gbk("2024年10月16日IP数据")].concat(),
+            ),
+        ])
+    }
+
+    fn place(db: &QQWry, ip: &str) -> Option<(Option<String>, Option<String>)> {
+        db.lookup(ip)
+            .map(|location| (location.country, location.isp))
+    }
+
+    #[test]
+    fn rejects_bad_headers() {
+        let data = sample();
+        let mut short = data.clone();
+        short.truncate(6);
+        let mut reversed = data.clone();
+        reversed[..4].copy_from_slice(&(u32::MAX).to_le_bytes());
+        let mut misaligned = data.clone();
+        let last_index = read_u32(&data, 4).unwrap() - 1;
+        misaligned[4..8].copy_from_slice(&last_index.to_le_bytes());
+        let mut truncated = data.clone();
+        truncated.truncate(data.len() - 1);
+        for (name, data) in [
+            ("qqwry-short", short),
+            ("qqwry-reversed", reversed),
+            ("qqwry-misaligned", misaligned),
+            ("qqwry-truncated", truncated),
+        ] {
+            assert!(QQWry::new(&temp_file(name, &data)).is_err(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn finds_the_first_and_last_ranges() {
+        let db = open("qqwry-bounds", &sample());
+        let beijing = Some((Some("北京市".to_string()), Some("联通".to_string())));
+        assert_eq!(place(&db, "1.0.0.0"), beijing);
+        assert_eq!(place(&db, "1.0.0.255"), beijing);
+        assert_eq!(
+            place(&db, "255.255.255.255"),
+            Some((
+                Some("纯真网络".to_string()),
+                Some("2024年10月16日IP数据".to_string())
+            ))
+        );
+    }
+
+    #[test]
+    fn misses_addresses_outside_every_range() {
+        let db = open("qqwry-outside", &sample());
+        assert_eq!(place(&db, "0.255.255.255"), None);
+        assert_eq!(place(&db, "1.0.1.0"), None);
+        assert_eq!(place(&db, "200.0.0.0"), None);
+        assert_eq!(place(&db, "2001:db8::1"), None);
+    }
+
+    #[test]
+    fn maps_ipv4_mapped_addresses_and_drops_placeholders() {
+        let db = open("qqwry-mapped", &sample());
+        assert_eq!(
+            place(&db, "::ffff:2.0.0.1"),
+            Some((Some("上海市".to_string()), None))
+        );
+    }
+
+    #[test]
+    fn follows_redirects_in_the_file() {
+        // The strings of the first record start at 12, after the header and its end address
+        let data = build(&[
+            (
+                "1.0.0.0",
+                "1.0.0.255",
+                [gbk("北京市"), gbk("联通")].concat(),
+            ),
+            ("2.0.0.0", "2.0.0.255", vec![0x01, 12, 0, 0]),
+            (
+                "3.0.0.0",
+                "3.0.0.255",
+                [&[0x02, 12, 0, 0][..], &gbk("电信")].concat(),
+            ),
+            // Records without an area point it at offset zero
+            (
+                "4.0.0.0",
+                "4.0.0.255",
+                [&gbk("广州市")[..], &[0x02, 0, 0, 0]].concat(),
+            ),
+        ]);
+        let db = open("qqwry-redirects", &data);
+        assert_eq!(
+            place(&db, "2.0.0.1"),
+            Some((Some("北京市".to_string()), Some("联通".to_string())))
+        );
+        assert_eq!(
+            place(&db, "3.0.0.1"),
+            Some((Some("北京市".to_string()), Some("电信".to_string())))
+        );
+        assert_eq!(
+            place(&db, "4.0.0.1"),
+            Some((Some("广州市".to_string()), None))
+        );
+    }
+
+    #[test]
+    fn reads_the_release_date() {
+        let db = open("qqwry-metadata", &sample());
+        let metadata = db.metadata().unwrap();
+        // 2024-10-16T00:00:00Z
+        assert_eq!(metadata.build_epoch, 1_729_036_800);
+        assert_eq!(metadata.node_count, 3);
+    }
+}
@@ -0,0 +1,163 @@
+// The record layout shared by QQWry and the ZX IPv6 database: a country and an
+// area string, either of which may be stored once and pointed at from other records
+
+// A record whose strings live somewhere else
+const REDIRECT_MODE_1: u8 = 0x01;
+// A record whose country lives somewhere else, the area follows it
+const REDIRECT_MODE_2: u8 = 0x02;
+
+// The country and area of the record at `offset`, decoded with `decode`
+pub fn read_record(
+    data: &[u8],
+    mut offset: usize,
+    decode: fn(&[u8]) -> String,
+) -> Option<(String, String)> {
+    if *data.get(offset)? == REDIRECT_MODE_1 {
+        offset = read_u24(data, offset + 1)?;
+    }
+    if *data.get(offset)? == REDIRECT_MODE_2 {
+        let country = read_string(data, read_u24(data, offset + 1)?, decode)?.0;
+        let area = read_area(data, offset + 4, decode)?;
+        Some((country, area))
+    } else {
+        let (country, end) = read_string(data, offset, decode)?;
+        let area = read_area(data, end, decode)?;
+        Some((country, area))
+    }
+}
+
+fn read_area(data: &[u8], offset: usize, decode: fn(&[u8]) -> String) -> Option<String> {
+    match *data.get(offset)? {
+        REDIRECT_MODE_1 | REDIRECT_MODE_2 => match read_u24(data, offset + 1)? {
+            // Records without an area point at offset zero
+            0 => Some(String::new()),
+            target => Some(read_string(data, target, decode)?.0),
+        },
+        _ => Some(read_string(data, offset, decode)?.0),
+    }
+}
+
+// A NUL terminated string and the offset after it
+fn read_string(data: &[u8], offset: usize, decode: fn(&[u8]) -> String) -> Option<(String, usize)> {
+    let bytes = data.get(offset..)?;
+    let len = bytes.iter().position(|b| *b == 0)?;
+    Some((decode(&bytes[..len]), offset + len + 1))
+}
+
+pub fn read_u24(data: &[u8], offset: usize) -> Option<usize> {
+    let bytes = data.get(offset..offset + 3)?;
+    Some(bytes[0] as usize | (bytes[1] as usize) << 8 | (bytes[2] as usize) << 16)
+}
+
+// Both databases fill unknown fields with placeholders
+pub fn field(text: String) -> Option<String> {
+    let text = text.trim();
+    (!text.is_empty() && !text.contains("CZ88.NET")).then(|| text.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(bytes: &[u8]) -> String {
+        String::from_utf8_lossy(bytes).into_owned()
+    }
+
+    fn u24(offset: usize) -> [u8; 3] {
+        let bytes = (offset as u32).to_le_bytes();
+        [bytes[0], bytes[1], bytes[2]]
+    }
+
+    // Strings shared by the redirected records below
+    const COUNTRY: usize = 0; // "Country\0"
+    const AREA: usize = 8; // "Area\0"
+    const RECORD: usize = 13; // "Plain\0Area\0"
+
+    fn data() -> Vec<u8> {
+        let mut data = b"Country\0Area\0Plain\0Area\0".to_vec();
+        data.resize(64, 0xff);
+        data
+    }
+
+    fn record(data: &mut Vec<u8>, bytes: &[u8]) -> usize {
+        let offset = data.len();
+        data.extend_from_slice(bytes);
+        offset
+    }
+
+    #[test]
+    fn reads_plain_records() {
+        let data = data();
+        assert_eq!(
+            read_record(&data, RECORD, decode),
+            Some(("Plain".to_string(), "Area".to_string()))
+        );
+    }
+
+    #[test]
+    fn follows_redirect_mode_1() {
+        let mut data = data();
+        let offset = record(&mut data, &[&[REDIRECT_MODE_1][..], &u24(RECORD)].concat());
+        assert_eq!(
+            read_record(&data, offset, decode),
+            Some(("Plain".to_string(), "Area".to_string()))
+        );
+    }
+
+    #[test]
+    fn follows_redirect_mode_2() {
+        let mut data = data();
+        let bytes = [&[REDIRECT_MODE_2][..], &u24(COUNTRY), b"Local\0"].concat();
+        let offset = record(&mut data, &bytes);
+        assert_eq!(
+            read_record(&data, offset, decode),
+            Some(("Country".to_string(), "Local".to_string()))
+        );
+    }
+
+    #[test]
+    fn follows_mode_1_into_mode_2_with_a_redirected_area() {
+        let mut data = data();
+        let bytes = [
+            &[REDIRECT_MODE_2][..],
+            &u24(COUNTRY),
+            &[REDIRECT_MODE_1],
+            &u24(AREA),
+        ]
+        .concat();
+        let target = record(&mut data, &bytes);
+        let offset = record(&mut data, &[&[REDIRECT_MODE_1][..], &u24(target)].concat());
+        assert_eq!(
+            read_record(&data, offset, decode),
+            Some(("Country".to_string(), "Area".to_string()))
+        );
+    }
+
+    #[test]
+    fn reads_an_area_pointing_at_zero_as_empty() {
+        let mut data = data();
+        let bytes = [&b"Plain\0"[..], &[REDIRECT_MODE_2], &u24(0)].concat();
+        let offset = record(&mut data, &bytes);
+        assert_eq!(
+            read_record(&data, offset, decode),
+            Some(("Plain".to_string(), String::new()))
+        );
+    }
+
+    #[test]
+    fn rejects_records_past_the_end() {
+        let mut data = data();
+        let offset = record(&mut data, &[&[REDIRECT_MODE_1][..], &u24(1 << 20)].concat());
+        assert_eq!(read_record(&data, offset, decode), None);
+        // An unterminated string
+        let offset = record(&mut data, b"Open");
+        assert_eq!(read_record(&data, offset, decode), None);
+    }
+
+    #[test]
+    fn drops_placeholders() {
+        assert_eq!(field(" CZ88.NET".to_string()), None);
+        assert_eq!(field("  ".to_string()), None);
+        assert_eq!(field(" Area ".to_string()), Some("Area".to_string()));
+    }
+}
@@ -0,0 +1,291 @@
+use std::{fs, net::IpAddr};
+
+use super::{
+    geodb::{epoch_from_civil, DbMetadata, GeoDB, GeoLocation},
+    wry::{self, field},
+};
+use crate::error::{NaliError, Result};
+
+const HEADER_SIZE: usize = 24;
+
+// The ZX IPv6 database (`ipv6wry.db`) in IPDB format. Its index maps the upper
+// 64 bits of an address to QQWry style records holding UTF-8 strings
+pub struct ZXIPv6Wry {
+    data: Vec<u8>,
+    // Bytes of the address prefix in an index entry
+    ip_len: usize,
+    // Bytes of the record offset in an index entry
+    offset_len: usize,
+    index_start: usize,
+    count: usize,
+}
+
+impl ZXIPv6Wry {
+    pub fn new(path: &str) -> Result<Self> {
+        let data = fs::read(path)
+            .map_err(|e| NaliError::Database(format!("Failed to open {}: {}", path, e)))?;
+        let invalid = || NaliError::Database(format!("{} is not an IPDB database", path));
+        if data.len() < HEADER_SIZE || &data[..4] != b"IPDB" {
+            return Err(invalid());
+        }
+        let offset_len = data[6] as usize;
+        let ip_len = data[7] as usize;
+        let count = read_u64(&data, 8).ok_or_else(invalid)? as usize;
+        let index_start = read_u64(&data, 16).ok_or_else(invalid)? as usize;
+        let entry_len = ip_len + offset_len;
+        if !(1..=8).contains(&ip_len)
+            || !(1..=8).contains(&offset_len)
+            || count == 0
+            || count
+                .checked_mul(entry_len)
+                .and_then(|len| len.checked_add(index_start))
+                .map_or(true, |end| end > data.len())
+        {
+            return Err(invalid());
+        }
+        Ok(Self {
+            data,
+            ip_len,
+            offset_len,
+            index_start,
+            count,
+        })
+    }
+
+    // The address prefix and record offset of an index entry
+    fn index_entry(&self, i: usize) -> (u64, usize) {
+        let offset = self.index_start + i * (self.ip_len + self.offset_len);
+        let ip = read_le(&self.data[offset..offset + self.ip_len]);
+        let record = read_le(&self.data[offset + self.ip_len..][..self.offset_len]);
+        (ip, record as usize)
+    }
+
+    // The record of the last range starting at or before `ip`
+    fn find(&self, ip: u64) -> Option<usize> {
+        let (mut low, mut high) = (0, self.count);
+        while high - low > 1 {
+            let mid = (low + high) / 2;
+            if self.index_entry(mid).0 <= ip {
+                low = mid;
+            } else {
+                high = mid;
+            }
+        }
+        let (start, record) = self.index_entry(low);
+        (start <= ip).then_some(record)
+    }
+
+    fn read_record(&self, record: usize) -> Option<(String, String)> {
+        wry::read_record(&self.data, record, |bytes| {
+            String::from_utf8_lossy(bytes).into_owned()
+        })
+    }
+
+    // The last record names the release, e.g. `ZX公网IPv6库` / `20210510`
+    fn release_date(&self) -> Option<(i64, u32, u32)> {
+        let (_, record) = self.index_entry(self.count - 1);
+        let (_, area) = self.read_record(record)?;
+        let digits = area.trim();
+        if digits.len() != 8 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        Some((
+            digits[..4].parse().ok()?,
+            digits[4..6].parse().ok()?,
+            digits[6..].parse().ok()?,
+        ))
+    }
+}
+
+fn read_u64(data: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(
+        data.get(offset..offset + 8)?.try_into().ok()?,
+    ))
+}
+
+fn read_le(bytes: &[u8]) -> u64 {
+    bytes
+        .iter()
+        .rev()
+        .fold(0, |value, byte| value << 8 | *byte as u64)
+}
+
+impl GeoDB for ZXIPv6Wry {
+    fn lookup(&self, ip: &str) -> Option<GeoLocation> {
+        // Drop the zone index of link-local addresses, it is not part of the address
+        let ip = match ip.split('%').next()?.parse().ok()? {
+            IpAddr::V6(ip) => ip,
+            // The database only covers IPv6
+            IpAddr::V4(_) => return None,
+        };
+        // Index entries hold the upper `ip_len` bytes of the address
+        let prefix = (u128::from(ip) >> (128 - 8 * self.ip_len)) as u64;
+        let (country, area) = self.read_record(self.find(prefix)?)?;
+        // Places are tab separated, e.g. `中国\t浙江省\t杭州市`
+        let mut place = country
+            .split('\t')
+            .filter_map(|part| field(part.to_string()));
+        let country = place.next();
+        let mut subdivisions: Vec<String> = place.collect();
+        let city = if subdivisions.len() > 1 {
+            subdivisions.pop()
+        } else {
+            None
+        };
+        let geo_location = GeoLocation {
+            country,
+            subdivisions,
+            city,
+            isp: field(area),
+            ..GeoLocation::default()
+        };
+        (!geo_location.is_empty()).then_some(geo_location)
+    }
+
+    // Only known when the release record names a date
+    fn metadata(&self) -> Option<DbMetadata> {
+        let (year, month, day) = self.release_date()?;
+        Some(DbMetadata {
+            database_type: "ZX IPv6".to_string(),
+            build_epoch: epoch_from_civil(year, month, day)?,
+            ip_version: 6,
+            node_count: u32::try_from(self.count).unwrap_or(u32::MAX),
+            languages: vec!["zh-CN".to_string()],
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv6Addr;
+
+    use super::*;
+    use crate::geo::temp_file;
+
+    // An IPDB file whose index entries have `ip_len` address bytes and
+    // `offset_len` record offset bytes, ranges are `(start, place, area)`
+    fn ipdb(ip_len: usize, offset_len: usize, ranges: &[(&str, &str, &str)]) -> Vec<u8> {
+        let mut data = b"IPDB\x02\x00".to_vec();
+        data.extend_from_slice(&[offset_len as u8, ip_len as u8]);
+        data.resize(HEADER_SIZE, 0);
+        let mut index = Vec::new();
+        for (start, place, area) in ranges {
+            let start = u128::from(start.parse::<Ipv6Addr>().unwrap());
+            let prefix = (start >> (128 - 8 * ip_len)) as u64;
+            index.extend_from_slice(&prefix.to_le_bytes()[..ip_len]);
+            index.extend_from_slice(&(data.len() as u64).to_le_bytes()[..offset_len]);
+            data.extend_from_slice(format!("{}\0{}\0", place, area).as_bytes());
+        }
+        let index_start = data.len() as u64;
+        data[8..16].copy_from_slice(&(ranges.len() as u64).to_le_bytes());
+        data[16..24].copy_from_slice(&index_start.to_le_bytes());
+        data.extend_from_slice(&index);
+        data
+    }
+
+    const RANGES: &[(&str, &str, &str)] = &[
+        ("2001:250::", "中国\t北京市", "CERNET2"),
+        ("2001:251::", "IANA\t保留地址", "ZX"),
+        ("2400:3200::", "中国\t浙江省\t杭州市", "阿里云"),
+        ("2400:3201::", "中国", " "),
+        ("fe80::", "局域网", "链路本地地址"),
+        ("ffff:ffff:ffff:ff00::", "ZX公网IPv6库", "20210510"),
+    ];
+
+    fn place(db: &ZXIPv6Wry, ip: &str) -> Option<(String, Vec<String>, Option<String>)> {
+        let geo_location = db.lookup(ip)?;
+        Some((
+            geo_location.country?,
+            geo_location.subdivisions,
+            geo_location.city,
+        ))
+    }
+
+    #[test]
+    fn reads_index_entries_of_any_width() {
+        // Released files use 8 address bytes and 3 offset bytes
+        for (ip_len, offset_len) in [(8, 3), (6, 4), (4, 8)] {
+            let data = ipdb(ip_len, offset_len, RANGES);
+            let name = format!("zx-width-{}-{}", ip_len, offset_len);
+            let db = ZXIPv6Wry::new(&temp_file(&name, &data)).unwrap();
+            assert_eq!(
+                db.lookup("2400:3200::1").unwrap().isp.as_deref(),
+                Some("阿里云")
+            );
+            assert_eq!(
+                db.lookup("2001:250:ffff::").unwrap().isp.as_deref(),
+                Some("CERNET2")
+            );
+            assert_eq!(db.lookup("2001:24f:ffff::"), None);
+        }
+        // 2 address bytes cannot tell apart ranges sharing the upper 16 bits
+        let db = ZXIPv6Wry::new(&temp_file("zx-narrow", &ipdb(2, 3, RANGES))).unwrap();
+        assert_eq!(db.lookup("2001:251::"), db.lookup("2001:250::"));
+        let db = ZXIPv6Wry::new(&temp_file("zx-wide", &ipdb(8, 3, RANGES))).unwrap();
+        assert_ne!(db.lookup("2001:251::"), db.lookup("2001:250::"));
+    }
+
+    #[test]
+    fn rejects_unusable_entry_widths() {
+        for (ip_len, offset_len) in [(0, 3), (9, 3), (8, 0), (8, 9)] {
+            let mut data = ipdb(8, 3, RANGES);
+            data[6] = offset_len;
+            data[7] = ip_len;
+            let name = format!("zx-bad-width-{}-{}", ip_len, offset_len);
+            assert!(
+                ZXIPv6Wry::new(&temp_file(&name, &data)).is_err(),
+                "{}",
+                name
+            );
+        }
+        // An index running past the end of the file
+        let mut data = ipdb(8, 3, RANGES);
+        data.pop();
+        assert!(ZXIPv6Wry::new(&temp_file("zx-truncated", &data)).is_err());
+        data[..4].copy_from_slice(b"IPDX");
+        assert!(ZXIPv6Wry::new(&temp_file("zx-magic", &data)).is_err());
+    }
+
+    #[test]
+    fn splits_tab_separated_places() {
+        let db = ZXIPv6Wry::new(&temp_file("zx-places", &ipdb(8, 3, RANGES))).unwrap();
+        assert_eq!(
+            place(&db, "2001:250::1"),
+            Some(("中国".to_string(), vec!["北京市".to_string()], None))
+        );
+        assert_eq!(
+            place(&db, "2400:3200::1"),
+            Some((
+                "中国".to_string(),
+                vec!["浙江省".to_string()],
+                Some("杭州市".to_string())
+            ))
+        );
+        let blank_area = db.lookup("2400:3201::1").unwrap();
+        assert_eq!(blank_area.country.as_deref(), Some("中国"));
+        assert_eq!(blank_area.isp, None);
+    }
+
+    #[test]
+    fn leaves_ipv4_to_other_databases() {
+        let db = ZXIPv6Wry::new(&temp_file("zx-ipv4", &ipdb(8, 3, RANGES))).unwrap();
+        assert_eq!(db.lookup("1.1.1.1"), None);
+        assert_eq!(db.lookup("::ffff:1.1.1.1"), None);
+        // The zone index is not part of the address
+        assert_eq!(
+            db.lookup("fe80::1%eth0").unwrap().country.as_deref(),
+            Some("局域网")
+        );
+    }
+
+    #[test]
+    fn dates_the_release_from_the_last_record() {
+        let db = ZXIPv6Wry::new(&temp_file("zx-date", &ipdb(8, 3, RANGES))).unwrap();
+        // 2021-05-10T00:00:00Z
+        assert_eq!(db.metadata().unwrap().build_epoch, 1_620_604_800);
+        let mut ranges = RANGES.to_vec();
+        ranges.push(("ffff:ffff:ffff:ffff::", "ZX公网IPv6库", "2021-05-10"));
+        let db = ZXIPv6Wry::new(&temp_file("zx-no-date", &ipdb(8, 3, &ranges))).unwrap();
+        assert_eq!(db.metadata(), None);
+    }
+}
@@ -1,54 +1,84 @@
-use std::fmt::Display;
+use std::{fmt::Display, sync::Arc};
 
+pub mod cli;
+pub mod commands;
 pub mod config;
+pub mod custom;
+pub mod diag;
+pub mod error;
 pub mod fast_parser;
+pub mod filter;
 pub mod geo;
+pub mod overlap;
 pub mod parser;
 pub mod regex_parser;
+pub mod registry;
+pub mod stream;
+pub mod template;
 pub mod token;
+pub mod update;
 
 use colored::Colorize;
 pub use fast_parser::FastParser;
-pub use parser::Parser;
+use geo::geodb::GeoLocation;
+pub use parser::{Parser, ParserOptions};
 pub use regex_parser::RegexParser;
+pub use template::Template;
 pub use token::Token;
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct NaliText {
     tokens: Vec<Token>,
+    // How locations are written, their `Display` when there is none
+    template: Option<Arc<Template>>,
 }
 
 impl NaliText {
     pub fn new(tokens: Vec<Token>) -> Self {
-        Self { tokens }
+        Self {
+            tokens,
+            template: None,
+        }
+    }
+
+    pub fn with_template(mut self, template: Option<Arc<Template>>) -> Self {
+        self.template = template;
+        self
     }
 
     pub fn tokens(&self) -> &[Token] {
         &self.tokens
     }
 
+    fn location(&self, geo: &Option<GeoLocation>) -> String {
+        let location = match (geo, &self.template) {
+            (Some(geo), Some(template)) => template.render(geo),
+            (Some(geo), None) => geo.to_string(),
+            (None, _) => String::new(),
+        };
+        if location.is_empty() {
+            "Unknown".to_string()
+        } else {
+            location
+        }
+    }
+
     pub fn colorize(&self) -> String {
         self.tokens
             .iter()
             .map(|token| match token {
                 Token::Plain(text) => text.to_string(),
-                Token::IPv4(addr, geo) => format!(
-                    "{} [{}]",
-                    addr.green(),
-                    match geo {
-                        Some(geo) => geo.location.red(),
-                        None => "Unknown".red(),
-                    }
-                ),
-                Token::IPv6(addr, geo) => format!(
-                    "{} [{}]",
-                    addr.blue(),
-                    match geo {
-                        Some(geo) => geo.location.red(),
-                        None => "Unknown".red(),
-                    }
-                ),
+                Token::IPv4(addr, geo) => {
+                    format!("{} [{}]", addr.green(), self.location(geo).red())
+                }
+                Token::IPv6(addr, geo) => {
+                    format!("{} [{}]", addr.blue(), self.location(geo).red())
+                }
                 Token::Domain(domain) => domain.yellow().to_string(),
+                Token::Custom(_, text, value) => match value {
+                    Some(value) => format!("{} [{}]", text.cyan(), value.red()),
+                    None => text.cyan().to_string(),
+                },
             })
             .collect()
     }
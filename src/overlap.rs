@@ -0,0 +1,292 @@
+use std::cmp::Ordering;
+
+use serde::{Deserialize, Serialize};
+
+use crate::token::TokenKind;
+
+// A candidate token found by a parser, before overlapping candidates are resolved
+#[derive(Debug, Clone, PartialEq)]
+pub struct Match {
+    pub start: usize,
+    pub end: usize,
+    pub kind: TokenKind,
+}
+
+impl Match {
+    pub fn new(start: usize, end: usize, kind: TokenKind) -> Self {
+        Self { start, end, kind }
+    }
+
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    // Check if two ranges are overlapping
+    fn overlaps(&self, other: &Match) -> bool {
+        self.start < other.end && other.start < self.end
+    }
+}
+
+// How to pick a winner when two candidates cover the same text
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub enum OverlapStrategy {
+    // Custom > IPv4 > IPv6 > Domain, ties go to the leftmost candidate
+    #[default]
+    Priority,
+    // The longest candidate wins, ties are broken by priority
+    LongestMatch,
+    // The leftmost candidate wins, ties are broken by length and then priority
+    LeftmostLongest,
+    // Kinds listed first win, unlisted kinds lose to every listed one
+    Custom(Vec<TokenKind>),
+}
+
+impl OverlapStrategy {
+    // Decide which match to keep when two matches are overlapping,
+    // `first` never starts after `second`
+    fn keep_first(&self, first: &Match, second: &Match) -> bool {
+        let by_priority = || first.kind.priority().cmp(&second.kind.priority());
+        let ordering = match self {
+            OverlapStrategy::Priority => by_priority(),
+            OverlapStrategy::LongestMatch => first.len().cmp(&second.len()).then_with(by_priority),
+            OverlapStrategy::LeftmostLongest => second
+                .start
+                .cmp(&first.start)
+                .then_with(|| first.len().cmp(&second.len()))
+                .then_with(by_priority),
+            OverlapStrategy::Custom(order) => {
+                let rank = |kind: &TokenKind| order.iter().position(|k| k == kind);
+                // `None` means unlisted, which ranks below every listed kind
+                match (rank(&first.kind), rank(&second.kind)) {
+                    (Some(a), Some(b)) => b.cmp(&a),
+                    (Some(_), None) => Ordering::Greater,
+                    (None, Some(_)) => Ordering::Less,
+                    (None, None) => Ordering::Equal,
+                }
+            }
+        };
+        // Same rank, keep the first one
+        ordering != Ordering::Less
+    }
+
+    // Drop matches that lose against an overlapping one,
+    // the result is sorted by start position and free of overlaps
+    pub fn resolve(&self, mut matches: Vec<Match>) -> Vec<Match> {
+        // Sort matches by start position
+        matches.sort_by_key(|m| m.start);
+
+        let mut filtered_matches = Vec::new();
+        let mut i = 0;
+        while i < matches.len() {
+            let current = &matches[i];
+            let mut should_add = true;
+            let mut j = i + 1;
+
+            while j < matches.len() && current.overlaps(&matches[j]) {
+                if !self.keep_first(current, &matches[j]) {
+                    should_add = false;
+                    break;
+                }
+                j += 1;
+            }
+
+            if should_add {
+                filtered_matches.push(current.clone());
+                while j < matches.len() && current.overlaps(&matches[j]) {
+                    j += 1;
+                }
+                i = j;
+            } else {
+                i += 1;
+            }
+        }
+
+        filtered_matches
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        geo::fakegeo::FakeGeo, token::Token, FastParser, Parser, ParserOptions, RegexParser,
+    };
+
+    // `1.2.3.4.example.com`, both candidates start at 0
+    fn dotted_domain() -> Vec<Match> {
+        vec![
+            Match::new(0, 19, TokenKind::Domain),
+            Match::new(0, 7, TokenKind::IPv4),
+        ]
+    }
+
+    // `::ffff:1.2.3.4`, the IPv4 candidate is the tail of the IPv6 one
+    fn mapped_ipv4() -> Vec<Match> {
+        vec![
+            Match::new(0, 14, TokenKind::IPv6),
+            Match::new(7, 14, TokenKind::IPv4),
+        ]
+    }
+
+    fn kinds(matches: Vec<Match>) -> Vec<(usize, usize, TokenKind)> {
+        matches
+            .into_iter()
+            .map(|m| (m.start, m.end, m.kind))
+            .collect()
+    }
+
+    #[test]
+    fn priority_keeps_the_highest_kind() {
+        let strategy = OverlapStrategy::Priority;
+        assert_eq!(
+            kinds(strategy.resolve(dotted_domain())),
+            [(0, 7, TokenKind::IPv4)]
+        );
+        assert_eq!(
+            kinds(strategy.resolve(mapped_ipv4())),
+            [(7, 14, TokenKind::IPv4)]
+        );
+    }
+
+    #[test]
+    fn longest_match_keeps_the_longest_candidate() {
+        let strategy = OverlapStrategy::LongestMatch;
+        assert_eq!(
+            kinds(strategy.resolve(dotted_domain())),
+            [(0, 19, TokenKind::Domain)]
+        );
+        assert_eq!(
+            kinds(strategy.resolve(mapped_ipv4())),
+            [(0, 14, TokenKind::IPv6)]
+        );
+        // Same length, priority decides
+        let tied = vec![
+            Match::new(0, 7, TokenKind::Domain),
+            Match::new(0, 7, TokenKind::IPv4),
+        ];
+        assert_eq!(kinds(strategy.resolve(tied)), [(0, 7, TokenKind::IPv4)]);
+    }
+
+    #[test]
+    fn leftmost_longest_prefers_the_earlier_start() {
+        let strategy = OverlapStrategy::LeftmostLongest;
+        // Same start, the longer candidate wins
+        assert_eq!(
+            kinds(strategy.resolve(dotted_domain())),
+            [(0, 19, TokenKind::Domain)]
+        );
+        // An earlier start wins over a longer candidate
+        let shifted = vec![
+            Match::new(0, 5, TokenKind::Domain),
+            Match::new(2, 14, TokenKind::IPv4),
+        ];
+        assert_eq!(
+            kinds(strategy.resolve(shifted)),
+            [(0, 5, TokenKind::Domain)]
+        );
+    }
+
+    #[test]
+    fn custom_follows_the_listed_order() {
+        let strategy = OverlapStrategy::Custom(vec![TokenKind::Domain, TokenKind::IPv6]);
+        assert_eq!(
+            kinds(strategy.resolve(dotted_domain())),
+            [(0, 19, TokenKind::Domain)]
+        );
+        assert_eq!(
+            kinds(strategy.resolve(mapped_ipv4())),
+            [(0, 14, TokenKind::IPv6)]
+        );
+        // Neither kind is listed, the first candidate stays
+        let strategy = OverlapStrategy::Custom(vec![TokenKind::IPv6]);
+        assert_eq!(
+            kinds(strategy.resolve(dotted_domain())),
+            [(0, 19, TokenKind::Domain)]
+        );
+    }
+
+    #[test]
+    fn keeps_matches_that_do_not_overlap() {
+        let matches = vec![
+            Match::new(8, 12, TokenKind::Domain),
+            Match::new(0, 7, TokenKind::IPv4),
+        ];
+        assert_eq!(
+            kinds(OverlapStrategy::Priority.resolve(matches)),
+            [(0, 7, TokenKind::IPv4), (8, 12, TokenKind::Domain)]
+        );
+    }
+
+    fn parse(parser: &dyn Parser<FakeGeo>, input: &str) -> Vec<(Option<TokenKind>, String)> {
+        parser
+            .parse(input, &FakeGeo::new())
+            .tokens()
+            .iter()
+            .map(|token| {
+                let text = match token {
+                    Token::Plain(text) | Token::Domain(text) => text,
+                    Token::IPv4(text, _) | Token::IPv6(text, _) => text,
+                    Token::Custom(_, text, _) => text,
+                };
+                (token.kind(), text.clone())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn parsers_share_the_strategy() {
+        let options = |overlap| ParserOptions {
+            overlap,
+            ..ParserOptions::default()
+        };
+        let regex = RegexParser::new(options(OverlapStrategy::Priority));
+        assert_eq!(
+            parse(&regex, "1.2.3.4.example.com"),
+            [
+                (Some(TokenKind::IPv4), "1.2.3.4".to_string()),
+                (None, ".example.com".to_string()),
+            ]
+        );
+        assert_eq!(
+            parse(&regex, "::ffff:1.2.3.4"),
+            [
+                (None, "::ffff:".to_string()),
+                (Some(TokenKind::IPv4), "1.2.3.4".to_string()),
+            ]
+        );
+        let regex = RegexParser::new(options(OverlapStrategy::LongestMatch));
+        assert_eq!(
+            parse(&regex, "1.2.3.4.example.com"),
+            [(Some(TokenKind::Domain), "1.2.3.4.example.com".to_string())]
+        );
+        assert_eq!(
+            parse(&regex, "::ffff:1.2.3.4"),
+            [(Some(TokenKind::IPv6), "::ffff:1.2.3.4".to_string())]
+        );
+
+        // The fast parser has neither domain nor IPv4-mapped candidates,
+        // both inputs keep their IPv4 part under every strategy
+        for overlap in [OverlapStrategy::Priority, OverlapStrategy::LongestMatch] {
+            let fast = FastParser::new(options(overlap));
+            assert_eq!(
+                parse(&fast, "1.2.3.4.example.com"),
+                [
+                    (Some(TokenKind::IPv4), "1.2.3.4".to_string()),
+                    (None, ".example.com".to_string()),
+                ]
+            );
+            assert_eq!(
+                parse(&fast, "::ffff:1.2.3.4"),
+                [
+                    (None, "::ffff:".to_string()),
+                    (Some(TokenKind::IPv4), "1.2.3.4".to_string()),
+                ]
+            );
+        }
+    }
+}
@@ -1,6 +1,131 @@
-use crate::{geo::geodb::GeoDB, NaliText};
+use std::sync::Arc;
+
+use crate::{
+    custom::CustomPattern,
+    error::{NaliError, Result},
+    filter::AnnotationFilter,
+    geo::geodb::GeoDB,
+    overlap::{Match, OverlapStrategy},
+    template::Template,
+    token::{KindSet, Token, TokenKind},
+    NaliText,
+};
 
 pub trait Parser<G: GeoDB> {
     fn parse(&self, input: &str, db: &G) -> NaliText;
     fn name(&self) -> &str;
 }
+
+// Options shared by every parser implementation
+#[derive(Debug, Clone, Default)]
+pub struct ParserOptions {
+    pub overlap: OverlapStrategy,
+    pub custom: Vec<CustomPattern>,
+    pub filter: AnnotationFilter,
+    // Kinds the parser looks for, undetected kinds can't win overlaps
+    pub detect: KindSet,
+    // Detected kinds that get annotated, the others are left as plain text
+    pub annotate: KindSet,
+    // How locations are written, see `Template`
+    pub template: Option<Arc<Template>>,
+}
+
+impl ParserOptions {
+    pub fn detects(&self, kind: &TokenKind) -> bool {
+        self.detect.contains(kind)
+    }
+
+    // Fail on `detect` and `annotate` kinds that are neither built in nor a custom pattern,
+    // they would otherwise silently match nothing
+    pub fn check_kinds(&self) -> Result<()> {
+        let known: Vec<TokenKind> = [TokenKind::IPv4, TokenKind::IPv6, TokenKind::Domain]
+            .into_iter()
+            .chain(
+                self.custom
+                    .iter()
+                    .map(|pattern| TokenKind::Custom(pattern.name().to_string())),
+            )
+            .collect();
+        for (setting, kinds) in [("detect", &self.detect), ("annotate", &self.annotate)] {
+            if let Some(kind) = kinds.kinds().iter().find(|kind| !known.contains(kind)) {
+                return Err(NaliError::Config(format!(
+                    "Unknown token kind {} in {}, available: {}",
+                    String::from(kind.clone()),
+                    setting,
+                    known
+                        .into_iter()
+                        .map(String::from)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    // Collect the matches of every user-defined pattern
+    pub fn match_custom(&self, input: &str, matches: &mut Vec<Match>) {
+        for pattern in &self.custom {
+            let kind = TokenKind::Custom(pattern.name().to_string());
+            if !self.detects(&kind) {
+                continue;
+            }
+            // Patterns like `z*` also match the empty string between characters
+            for custom_match in pattern.regex().find_iter(input) {
+                if custom_match.is_empty() {
+                    continue;
+                }
+                matches.push(Match::new(
+                    custom_match.start(),
+                    custom_match.end(),
+                    kind.clone(),
+                ));
+            }
+        }
+    }
+
+    fn custom_value(&self, name: &str, text: &str) -> Option<String> {
+        self.custom
+            .iter()
+            .find(|pattern| pattern.name() == name)
+            .and_then(|pattern| pattern.lookup(text))
+    }
+
+    // Resolve overlapping matches and turn the survivors into a token sequence,
+    // the database is only queried for tokens that end up in the output
+    pub fn build_text<G: GeoDB>(&self, input: &str, matches: Vec<Match>, db: &G) -> NaliText {
+        let mut tokens = Vec::new();
+        let mut last_end = 0;
+
+        // Filtered tokens still win their overlaps but stay in the plain text
+        let annotated = self.overlap.resolve(matches).into_iter().filter(|m| {
+            self.annotate.contains(&m.kind)
+                && self.filter.should_annotate(&m.kind, &input[m.start..m.end])
+        });
+
+        // Construct the final token sequence
+        for m in annotated {
+            if m.start > last_end {
+                tokens.push(Token::Plain(input[last_end..m.start].to_string()));
+            }
+            let text = &input[m.start..m.end];
+            tokens.push(match m.kind {
+                TokenKind::IPv4 => Token::IPv4(text.to_string(), db.lookup(text)),
+                TokenKind::IPv6 => Token::IPv6(text.to_string(), db.lookup(text)),
+                TokenKind::Domain => Token::Domain(text.to_string()),
+                TokenKind::Custom(name) => {
+                    let value = self.custom_value(&name, text);
+                    Token::Custom(name, text.to_string(), value)
+                }
+            });
+            last_end = m.end;
+        }
+
+        // Add the remaining plain text
+        if last_end < input.len() {
+            tokens.push(Token::Plain(input[last_end..].to_string()));
+        }
+
+        NaliText::new(tokens).with_template(self.template.clone())
+    }
+}
@@ -0,0 +1,190 @@
+use std::collections::BTreeMap;
+
+use crate::{
+    config::{GeoDBConfig, NaliConfig},
+    error::{NaliError, Result},
+    geo::{
+        composite::{Composite, CompositeBackend},
+        fakegeo::FakeGeo,
+        geodb::GeoDB,
+        geolite2::GeoLite2,
+        geolite2_asn::GeoLite2ASN,
+        ip2location::IP2Location,
+        ip2region::Ip2Region,
+        qqwry::QQWry,
+        zxipv6wry::ZXIPv6Wry,
+    },
+    FastParser, Parser, ParserOptions, RegexParser,
+};
+
+pub type DynParser = Box<dyn Parser<Box<dyn GeoDB>>>;
+pub type ParserFactory = Box<dyn Fn(ParserOptions) -> DynParser>;
+// The registry itself is passed along to build nested databases, such as the backends of a composite
+pub type GeoDBFactory = Box<dyn Fn(&GeoDBConfig, &NaliConfig, &Registry) -> Result<Box<dyn GeoDB>>>;
+
+// Maps the names used in the config file to parser and database implementations
+pub struct Registry {
+    parsers: BTreeMap<String, ParserFactory>,
+    geodbs: BTreeMap<String, GeoDBFactory>,
+}
+
+// The registry with every built-in implementation
+impl Default for Registry {
+    fn default() -> Self {
+        let mut registry = Self::empty();
+        registry.register_parser("fast_parser", |options| Box::new(FastParser::new(options)));
+        registry.register_parser("regex_parser", |options| {
+            Box::new(RegexParser::new(options))
+        });
+        registry.register_geodb("fake_geo", |_, _, _| Ok(Box::new(FakeGeo::new())));
+        registry.register_geodb("geo_lite2", |config, nali_config, _| match config {
+            GeoDBConfig::GeoLite2(geolite2_config) => {
+                let app_support_path = nali_config.app_support_path();
+                let mut geolite2 = GeoLite2::new(&geolite2_config.full_path(app_support_path))?;
+                if let Some(asn_path) = geolite2_config.full_asn_path(app_support_path) {
+                    geolite2 = geolite2.with_asn(GeoLite2ASN::new(&asn_path)?);
+                }
+                Ok(Box::new(geolite2))
+            }
+            _ => Err(NaliError::Config(
+                "geo_lite2 requires a geo_lite2 config".to_string(),
+            )),
+        });
+        registry.register_geodb("geo_lite2_asn", |config, nali_config, _| match config {
+            GeoDBConfig::GeoLite2ASN(asn_config) => Ok(Box::new(GeoLite2ASN::new(
+                &asn_config.full_path(nali_config.app_support_path()),
+            )?)),
+            _ => Err(NaliError::Config(
+                "geo_lite2_asn requires a geo_lite2_asn config".to_string(),
+            )),
+        });
+        registry.register_geodb("qqwry", |config, nali_config, _| match config {
+            GeoDBConfig::QQWry(qqwry_config) => Ok(Box::new(QQWry::new(
+                &qqwry_config.full_path(nali_config.app_support_path()),
+            )?)),
+            _ => Err(NaliError::Config(
+                "qqwry requires a qqwry config".to_string(),
+            )),
+        });
+        registry.register_geodb("zxipv6wry", |config, nali_config, _| match config {
+            GeoDBConfig::ZXIPv6Wry(zx_config) => Ok(Box::new(ZXIPv6Wry::new(
+                &zx_config.full_path(nali_config.app_support_path()),
+            )?)),
+            _ => Err(NaliError::Config(
+                "zxipv6wry requires a zxipv6wry config".to_string(),
+            )),
+        });
+        registry.register_geodb("ip2region", |config, nali_config, _| match config {
+            GeoDBConfig::Ip2Region(ip2region_config) => Ok(Box::new(Ip2Region::new(
+                &ip2region_config.full_path(nali_config.app_support_path()),
+                ip2region_config.mode,
+            )?)),
+            _ => Err(NaliError::Config(
+                "ip2region requires an ip2region config".to_string(),
+            )),
+        });
+        registry.register_geodb("ip2location", |config, nali_config, _| match config {
+            GeoDBConfig::IP2Location(ip2location_config) => Ok(Box::new(IP2Location::new(
+                &ip2location_config.full_path(nali_config.app_support_path()),
+            )?)),
+            _ => Err(NaliError::Config(
+                "ip2location requires an ip2location config".to_string(),
+            )),
+        });
+        registry.register_geodb("composite", |config, nali_config, registry| match config {
+            GeoDBConfig::Composite(composite_config) => {
+                if composite_config.backends.is_empty() {
+                    return Err(NaliError::Config(
+                        "composite requires at least one backend".to_string(),
+                    ));
+                }
+                let backends = composite_config
+                    .backends
+                    .iter()
+                    .map(|backend| {
+                        if let Some(ip_version) = backend.ip_version.filter(|v| *v != 4 && *v != 6)
+                        {
+                            return Err(NaliError::Config(format!(
+                                "Invalid ip_version {} for {}, expected 4 or 6",
+                                ip_version,
+                                backend.db.name()
+                            )));
+                        }
+                        Ok(CompositeBackend {
+                            db: registry.build_geodb(&backend.db, nali_config)?,
+                            ip_version: backend.ip_version,
+                        })
+                    })
+                    .collect::<Result<_>>()?;
+                Ok(Box::new(Composite::new(
+                    composite_config.strategy,
+                    backends,
+                )))
+            }
+            _ => Err(NaliError::Config(
+                "composite requires a composite config".to_string(),
+            )),
+        });
+        registry
+    }
+}
+
+impl Registry {
+    // A registry without any implementation
+    pub fn empty() -> Self {
+        Self {
+            parsers: BTreeMap::new(),
+            geodbs: BTreeMap::new(),
+        }
+    }
+
+    // Register a parser, replacing any previous one with the same name
+    pub fn register_parser<F>(&mut self, name: &str, factory: F)
+    where
+        F: Fn(ParserOptions) -> DynParser + 'static,
+    {
+        self.parsers.insert(name.to_string(), Box::new(factory));
+    }
+
+    // Register a database, replacing any previous one with the same name
+    pub fn register_geodb<F>(&mut self, name: &str, factory: F)
+    where
+        F: Fn(&GeoDBConfig, &NaliConfig, &Registry) -> Result<Box<dyn GeoDB>> + 'static,
+    {
+        self.geodbs.insert(name.to_string(), Box::new(factory));
+    }
+
+    pub fn parser_names(&self) -> impl Iterator<Item = &str> {
+        self.parsers.keys().map(String::as_str)
+    }
+
+    pub fn geodb_names(&self) -> impl Iterator<Item = &str> {
+        self.geodbs.keys().map(String::as_str)
+    }
+
+    pub fn build_parser(&self, name: &str, options: ParserOptions) -> Result<DynParser> {
+        let factory = self.parsers.get(name).ok_or_else(|| {
+            NaliError::Config(format!(
+                "Unknown parser {}, available: {}",
+                name,
+                self.parser_names().collect::<Vec<_>>().join(", ")
+            ))
+        })?;
+        Ok(factory(options))
+    }
+
+    pub fn build_geodb(
+        &self,
+        config: &GeoDBConfig,
+        nali_config: &NaliConfig,
+    ) -> Result<Box<dyn GeoDB>> {
+        let factory = self.geodbs.get(config.name()).ok_or_else(|| {
+            NaliError::Config(format!(
+                "Unknown database {}, available: {}",
+                config.name(),
+                self.geodb_names().collect::<Vec<_>>().join(", ")
+            ))
+        })?;
+        factory(config, nali_config, self)
+    }
+}
@@ -0,0 +1,412 @@
+use std::{
+    io::{self, BufRead, ErrorKind, Read, Write},
+    sync::mpsc::{self, RecvTimeoutError},
+    thread,
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{geo::geodb::GeoDB, parser::Parser};
+
+// How line terminators are written back
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum LineEndings {
+    // Preserve when the input is piped, normalize when it is a terminal
+    #[default]
+    Auto,
+    // Keep `\n`, `\r\n` and lone `\r` exactly as read, never add a final newline
+    Preserve,
+    // Split on `\n` only and end every line with `\n`
+    Normalize,
+}
+
+impl LineEndings {
+    pub fn preserves(&self, input_is_terminal: bool) -> bool {
+        match self {
+            LineEndings::Auto => !input_is_terminal,
+            LineEndings::Preserve => true,
+            LineEndings::Normalize => false,
+        }
+    }
+}
+
+// Annotate the valid UTF-8 regions of `input` and pass every other byte through untouched
+pub fn annotate_bytes<G: GeoDB, W: Write + ?Sized>(
+    parser: &dyn Parser<G>,
+    db: &G,
+    input: &[u8],
+    output: &mut W,
+) -> io::Result<()> {
+    for chunk in input.utf8_chunks() {
+        if !chunk.valid().is_empty() {
+            let nali_text = parser.parse(chunk.valid(), db);
+            output.write_all(nali_text.colorize().as_bytes())?;
+        }
+        output.write_all(chunk.invalid())?;
+    }
+    Ok(())
+}
+
+// Split a record into its content and its terminator
+fn split_terminator(record: &[u8]) -> (&[u8], &[u8]) {
+    let terminator_len = if record.ends_with(b"\r\n") {
+        2
+    } else if record.ends_with(b"\n") || record.ends_with(b"\r") {
+        1
+    } else {
+        0
+    };
+    record.split_at(record.len() - terminator_len)
+}
+
+// Append one record to `buf`, terminator included, records end at `\n`, `\r\n` or a lone `\r`.
+// A `\r` record is returned as soon as the buffered input is used up, so progress updates
+// aren't held until the next byte arrives; a `\n` read later is then a record of its own
+fn read_record<R: BufRead>(input: &mut R, buf: &mut Vec<u8>) -> io::Result<usize> {
+    let start_len = buf.len();
+    loop {
+        let available = match input.fill_buf() {
+            Ok(available) => available,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        if available.is_empty() {
+            break;
+        }
+        let (used, done) = match available.iter().position(|b| *b == b'\n' || *b == b'\r') {
+            // Take the `\n` of a `\r\n` as well when it is already buffered
+            Some(i) if available[i] == b'\r' && available.get(i + 1) == Some(&b'\n') => {
+                buf.extend_from_slice(&available[..i + 2]);
+                (i + 2, true)
+            }
+            Some(i) => {
+                buf.extend_from_slice(&available[..=i]);
+                (i + 1, true)
+            }
+            None => {
+                buf.extend_from_slice(available);
+                (available.len(), false)
+            }
+        };
+        input.consume(used);
+        if done {
+            break;
+        }
+    }
+    Ok(buf.len() - start_len)
+}
+
+// Annotate `input` record by record, either keeping every terminator as it was
+// or writing each line back with a `\n` terminator. Output is flushed after each
+// record when `line_buffered` is set, otherwise only once at the end.
+pub fn annotate_lines<G: GeoDB, R: BufRead, W: Write>(
+    parser: &dyn Parser<G>,
+    db: &G,
+    mut input: R,
+    mut output: W,
+    preserve_terminators: bool,
+    line_buffered: bool,
+) -> io::Result<()> {
+    let mut record = Vec::new();
+    loop {
+        let read = if preserve_terminators {
+            read_record(&mut input, &mut record)?
+        } else {
+            input.read_until(b'\n', &mut record)?
+        };
+        if read == 0 {
+            break;
+        }
+        if preserve_terminators {
+            let (content, terminator) = split_terminator(&record);
+            annotate_bytes(parser, db, content, &mut output)?;
+            output.write_all(terminator)?;
+        } else {
+            // Strip the terminator, `\r\n` included
+            let content = record.strip_suffix(b"\n").unwrap_or(&record);
+            let content = content.strip_suffix(b"\r").unwrap_or(content);
+            annotate_bytes(parser, db, content, &mut output)?;
+            output.write_all(b"\n")?;
+        }
+        if line_buffered {
+            output.flush()?;
+        }
+        record.clear();
+    }
+    output.flush()
+}
+
+// Whether a byte may be part of an address or a domain
+fn is_token_byte(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || matches!(b, b'.' | b':' | b'-' | b'%' | b'_')
+}
+
+// Whether `bytes` is the beginning of a multi-byte UTF-8 sequence
+fn is_incomplete_utf8(bytes: &[u8]) -> bool {
+    let width = match bytes.first() {
+        Some(0xC2..=0xDF) => 2,
+        Some(0xE0..=0xEF) => 3,
+        Some(0xF0..=0xF4) => 4,
+        _ => return false,
+    };
+    bytes.len() < width && bytes[1..].iter().all(|b| b & 0xC0 == 0x80)
+}
+
+// Where the tail of `pending` that could still grow into a longer token starts
+fn held_tail_start(pending: &[u8]) -> usize {
+    // An unfinished character is held on its own, what comes before it is complete
+    if let Some(chunk) = pending.utf8_chunks().last() {
+        if is_incomplete_utf8(chunk.invalid()) {
+            return pending.len() - chunk.invalid().len();
+        }
+    }
+    let mut start = pending.len();
+    while start > 0 && is_token_byte(pending[start - 1]) {
+        start -= 1;
+    }
+    start
+}
+
+// Annotate `input` as it arrives, plain text is written immediately and only a
+// tail that could still be part of an address is held back, until more input
+// arrives or `timeout` passes. Every byte, terminators included, is kept.
+pub fn annotate_stream<G, R, W>(
+    parser: &dyn Parser<G>,
+    db: &G,
+    input: R,
+    mut output: W,
+    timeout: Duration,
+) -> io::Result<()>
+where
+    G: GeoDB,
+    R: Read + Send + 'static,
+    W: Write,
+{
+    // Read on a separate thread so the held tail can be flushed on timeout
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut input = input;
+        let mut buf = vec![0; 8192];
+        loop {
+            let chunk = match input.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => Ok(buf[..n].to_vec()),
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => Err(e),
+            };
+            let failed = chunk.is_err();
+            if sender.send(chunk).is_err() || failed {
+                break;
+            }
+        }
+    });
+
+    let mut pending = Vec::new();
+    loop {
+        let received = if pending.is_empty() {
+            receiver.recv().ok()
+        } else {
+            match receiver.recv_timeout(timeout) {
+                Ok(chunk) => Some(chunk),
+                Err(RecvTimeoutError::Timeout) => {
+                    // Nothing more arrived, the held tail is complete
+                    annotate_bytes(parser, db, &pending, &mut output)?;
+                    output.flush()?;
+                    pending.clear();
+                    continue;
+                }
+                Err(RecvTimeoutError::Disconnected) => None,
+            }
+        };
+        match received {
+            Some(chunk) => {
+                pending.extend_from_slice(&chunk?);
+                let held = held_tail_start(&pending);
+                annotate_bytes(parser, db, &pending[..held], &mut output)?;
+                output.flush()?;
+                pending.drain(..held);
+            }
+            None => {
+                annotate_bytes(parser, db, &pending, &mut output)?;
+                output.flush()?;
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::VecDeque, io::BufReader};
+
+    use super::*;
+    use crate::{geo::fakegeo::FakeGeo, FastParser, ParserOptions};
+
+    // Hands out one chunk per read, the way a pipe delivers its writes,
+    // waiting `pause` before each chunk but the first
+    struct Chunks {
+        chunks: VecDeque<Vec<u8>>,
+        pause: Duration,
+        started: bool,
+    }
+
+    impl Chunks {
+        fn new(chunks: &[&[u8]]) -> Self {
+            Self::paused(chunks, Duration::ZERO)
+        }
+
+        fn paused(chunks: &[&[u8]], pause: Duration) -> Self {
+            Chunks {
+                chunks: chunks.iter().map(|chunk| chunk.to_vec()).collect(),
+                pause,
+                started: false,
+            }
+        }
+    }
+
+    impl Read for Chunks {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let Some(mut chunk) = self.chunks.pop_front() else {
+                return Ok(0);
+            };
+            if self.started {
+                thread::sleep(self.pause);
+            }
+            self.started = true;
+            let n = chunk.len().min(buf.len());
+            buf[..n].copy_from_slice(&chunk[..n]);
+            if n < chunk.len() {
+                self.chunks.push_front(chunk.split_off(n));
+            }
+            Ok(n)
+        }
+    }
+
+    fn lines(input: impl BufRead, preserve_terminators: bool) -> Vec<u8> {
+        colored::control::set_override(false);
+        let parser = FastParser::new(ParserOptions::default());
+        let mut output = Vec::new();
+        annotate_lines(
+            &parser,
+            &FakeGeo::new(),
+            input,
+            &mut output,
+            preserve_terminators,
+            false,
+        )
+        .unwrap();
+        output
+    }
+
+    fn preserved(input: &[u8]) -> Vec<u8> {
+        lines(io::Cursor::new(input.to_vec()), true)
+    }
+
+    #[test]
+    fn keeps_crlf_terminators() {
+        assert_eq!(
+            preserved(b"a 1.2.3.4\r\nb\r\n"),
+            b"a 1.2.3.4 [Fake Location]\r\nb\r\n"
+        );
+    }
+
+    #[test]
+    fn a_lone_carriage_return_ends_a_record() {
+        assert_eq!(
+            preserved(b"10% 1.2.3.4\r20% 5.6.7.8\r"),
+            b"10% 1.2.3.4 [Fake Location]\r20% 5.6.7.8 [Fake Location]\r"
+        );
+    }
+
+    #[test]
+    fn keeps_crlf_split_across_reads() {
+        let input = BufReader::new(Chunks::new(&[b"a 1.2.3.4\r", b"\nb\n"]));
+        assert_eq!(lines(input, true), b"a 1.2.3.4 [Fake Location]\r\nb\n");
+    }
+
+    #[test]
+    fn does_not_add_a_final_newline() {
+        assert_eq!(preserved(b"a\n1.2.3.4"), b"a\n1.2.3.4 [Fake Location]");
+    }
+
+    #[test]
+    fn passes_invalid_utf8_through() {
+        assert_eq!(
+            preserved(b"\xff1.2.3.4\xfe\r\n"),
+            b"\xff1.2.3.4 [Fake Location]\xfe\r\n"
+        );
+    }
+
+    #[test]
+    fn joins_a_character_split_across_reads() {
+        let input = BufReader::new(Chunks::new(&[b"\xe4\xb8", b"\xad 1.2.3.4\n"]));
+        assert_eq!(
+            lines(input, true),
+            "中 1.2.3.4 [Fake Location]\n".as_bytes()
+        );
+    }
+
+    #[test]
+    fn line_endings_on_mixed_input() {
+        let input = b"a 1.2.3.4\r\nb\nc";
+        let annotate = |line_endings: LineEndings, input_is_terminal| {
+            let preserve = line_endings.preserves(input_is_terminal);
+            lines(io::Cursor::new(input.to_vec()), preserve)
+        };
+        let preserved = b"a 1.2.3.4 [Fake Location]\r\nb\nc".to_vec();
+        let normalized = b"a 1.2.3.4 [Fake Location]\nb\nc\n".to_vec();
+        assert_eq!(annotate(LineEndings::Auto, false), preserved);
+        assert_eq!(annotate(LineEndings::Auto, true), normalized);
+        assert_eq!(annotate(LineEndings::Preserve, false), preserved);
+        assert_eq!(annotate(LineEndings::Preserve, true), preserved);
+        assert_eq!(annotate(LineEndings::Normalize, false), normalized);
+        assert_eq!(annotate(LineEndings::Normalize, true), normalized);
+    }
+
+    fn streamed(input: Chunks, timeout: Duration) -> Vec<u8> {
+        colored::control::set_override(false);
+        let parser = FastParser::new(ParserOptions::default());
+        let mut output = Vec::new();
+        annotate_stream(&parser, &FakeGeo::new(), input, &mut output, timeout).unwrap();
+        output
+    }
+
+    #[test]
+    fn holds_back_only_a_possible_token() {
+        assert_eq!(held_tail_start(b"ping 1.2.3"), 5);
+        assert_eq!(held_tail_start(b"ping 1.2.3 "), 11);
+        assert_eq!(held_tail_start(b"ping ::ff"), 5);
+        // An unfinished character is held without the token before it
+        assert_eq!(held_tail_start(b"1.2.3.4\xe4\xb8"), 7);
+    }
+
+    #[test]
+    fn completes_a_held_token_with_the_next_chunk() {
+        let input = Chunks::new(&[b"ping 1.2.", b"3.4 ok\n"]);
+        assert_eq!(
+            streamed(input, Duration::from_secs(60)),
+            b"ping 1.2.3.4 [Fake Location] ok\n"
+        );
+    }
+
+    #[test]
+    fn flushes_the_held_tail_on_timeout() {
+        // Without the timeout the two chunks would form `1.2.3.45`
+        let input = Chunks::paused(&[b"ping 1.2.3.4", b"5\n"], Duration::from_millis(500));
+        assert_eq!(
+            streamed(input, Duration::from_millis(20)),
+            b"ping 1.2.3.4 [Fake Location]5\n"
+        );
+    }
+
+    #[test]
+    fn flushes_the_held_tail_on_eof() {
+        let input = Chunks::new(&[b"ping ", b"1.2.3.4"]);
+        assert_eq!(
+            streamed(input, Duration::from_secs(60)),
+            b"ping 1.2.3.4 [Fake Location]"
+        );
+    }
+}
@@ -0,0 +1,308 @@
+use std::str::FromStr;
+
+use crate::geo::geodb::GeoLocation;
+
+// A field of `GeoLocation` that can be referenced as `{name}` in a template
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Field {
+    Continent,
+    Country,
+    CountryCode,
+    // The largest subdivision, e.g. the state
+    Subdivision,
+    // Every subdivision, joined with commas
+    Subdivisions,
+    City,
+    Postal,
+    Latitude,
+    Longitude,
+    AccuracyRadius,
+    TimeZone,
+    RegisteredCountry,
+    RepresentedCountry,
+    // Why the country may be misleading, e.g. `anycast`
+    Notes,
+    Asn,
+    Organization,
+    Isp,
+    ConnectionType,
+    UsageType,
+    Domain,
+}
+
+impl Field {
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "continent" => Field::Continent,
+            "country" => Field::Country,
+            "country_code" => Field::CountryCode,
+            "subdivision" => Field::Subdivision,
+            "subdivisions" => Field::Subdivisions,
+            "city" => Field::City,
+            "postal" => Field::Postal,
+            "latitude" => Field::Latitude,
+            "longitude" => Field::Longitude,
+            "accuracy_radius" => Field::AccuracyRadius,
+            "time_zone" => Field::TimeZone,
+            "registered_country" => Field::RegisteredCountry,
+            "represented_country" => Field::RepresentedCountry,
+            "notes" => Field::Notes,
+            "asn" => Field::Asn,
+            "organization" | "org" => Field::Organization,
+            "isp" => Field::Isp,
+            "connection_type" => Field::ConnectionType,
+            "usage_type" => Field::UsageType,
+            "domain" => Field::Domain,
+            _ => return None,
+        })
+    }
+
+    fn value(&self, geo: &GeoLocation) -> Option<String> {
+        match self {
+            Field::Continent => geo.continent.clone(),
+            Field::Country => geo.country.clone(),
+            Field::CountryCode => geo.country_code.clone(),
+            Field::Subdivision => geo.subdivisions.first().cloned(),
+            Field::Subdivisions => {
+                (!geo.subdivisions.is_empty()).then(|| geo.subdivisions.join(", "))
+            }
+            Field::City => geo.city.clone(),
+            Field::Postal => geo.postal.clone(),
+            Field::Latitude => geo.latitude.map(|latitude| latitude.to_string()),
+            Field::Longitude => geo.longitude.map(|longitude| longitude.to_string()),
+            Field::AccuracyRadius => geo.accuracy_radius.map(|radius| radius.to_string()),
+            Field::TimeZone => geo.time_zone.clone(),
+            Field::RegisteredCountry => geo.registered_country.clone(),
+            Field::RepresentedCountry => geo.represented_country.clone(),
+            Field::Notes => {
+                let notes = geo.notes();
+                (!notes.is_empty()).then(|| notes.join(", "))
+            }
+            Field::Asn => geo.asn.map(|asn| asn.to_string()),
+            Field::Organization => geo.organization.clone(),
+            Field::Isp => geo.isp.clone(),
+            Field::ConnectionType => geo.connection_type.clone(),
+            Field::UsageType => geo.usage_type.clone(),
+            Field::Domain => geo.domain.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Text(String),
+    Field(Field),
+    // `[...]`, dropped entirely when one of its fields is missing
+    Section(Vec<Part>),
+}
+
+// How a location is written, e.g. `{country_code}[ {city}][ | AS{asn}]`.
+// A missing field outside of a section renders as nothing; `\` escapes the
+// next character
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+impl Template {
+    // The location as text, empty when the template has nothing to show
+    pub fn render(&self, geo: &GeoLocation) -> String {
+        let mut output = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Field(field) => output.push_str(&field.value(geo).unwrap_or_default()),
+                // Text and sections always render
+                part => {
+                    Self::render_part(part, geo, &mut output);
+                }
+            }
+        }
+        output.trim().to_string()
+    }
+
+    // Appends the part, or returns None without appending when a field is missing
+    fn render_part(part: &Part, geo: &GeoLocation, output: &mut String) -> Option<()> {
+        match part {
+            Part::Text(text) => output.push_str(text),
+            Part::Field(field) => output.push_str(&field.value(geo)?),
+            Part::Section(parts) => {
+                let mut section = String::new();
+                let complete = parts
+                    .iter()
+                    .all(|part| Self::render_part(part, geo, &mut section).is_some());
+                // A missing field only drops its own section, not the enclosing one
+                if complete {
+                    output.push_str(&section);
+                }
+            }
+        }
+        Some(())
+    }
+}
+
+impl FromStr for Template {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        // The parts of every open section, the template itself at the bottom
+        let mut stack: Vec<Vec<Part>> = vec![Vec::new()];
+        let mut text = String::new();
+        let mut chars = s.chars();
+
+        fn flush(text: &mut String, parts: &mut Vec<Part>) {
+            if !text.is_empty() {
+                parts.push(Part::Text(std::mem::take(text)));
+            }
+        }
+
+        while let Some(c) = chars.next() {
+            let parts = stack
+                .last_mut()
+                .expect("the template level is never popped");
+            match c {
+                '\\' => match chars.next() {
+                    Some(escaped) => text.push(escaped),
+                    None => return Err(format!("Invalid template {:?}: trailing \\", s)),
+                },
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => return Err(format!("Invalid template {:?}: unclosed {{", s)),
+                        }
+                    }
+                    let field = Field::from_name(name.trim()).ok_or_else(|| {
+                        format!("Invalid template {:?}: unknown field {{{}}}", s, name)
+                    })?;
+                    flush(&mut text, parts);
+                    parts.push(Part::Field(field));
+                }
+                '[' => {
+                    flush(&mut text, parts);
+                    stack.push(Vec::new());
+                }
+                ']' => {
+                    if stack.len() == 1 {
+                        return Err(format!("Invalid template {:?}: unmatched ]", s));
+                    }
+                    let mut section = stack.pop().expect("checked above");
+                    flush(&mut text, &mut section);
+                    stack
+                        .last_mut()
+                        .expect("the template level is never popped")
+                        .push(Part::Section(section));
+                }
+                c => text.push(c),
+            }
+        }
+
+        if stack.len() > 1 {
+            return Err(format!("Invalid template {:?}: unclosed [", s));
+        }
+        let mut parts = stack.pop().expect("the template level is never popped");
+        flush(&mut text, &mut parts);
+        Ok(Template { parts })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parts(template: &str) -> Vec<Part> {
+        template.parse::<Template>().unwrap().parts
+    }
+
+    fn text(text: &str) -> Part {
+        Part::Text(text.to_string())
+    }
+
+    fn mountain_view() -> GeoLocation {
+        GeoLocation {
+            country_code: Some("US".to_string()),
+            subdivisions: vec!["California".to_string()],
+            city: Some("Mountain View".to_string()),
+            asn: Some(15169),
+            ..GeoLocation::default()
+        }
+    }
+
+    #[test]
+    fn parses_literal_text_and_fields() {
+        assert_eq!(parts("plain text"), [text("plain text")]);
+        assert_eq!(
+            parts("AS{asn} { org }"),
+            [
+                text("AS"),
+                Part::Field(Field::Asn),
+                text(" "),
+                Part::Field(Field::Organization),
+            ]
+        );
+        assert_eq!(parts(""), []);
+    }
+
+    #[test]
+    fn parses_escapes() {
+        assert_eq!(parts(r"\[\{city\}\]\\"), [text(r"[{city}]\")]);
+        assert_eq!(parts("a}b"), [text("a}b")]);
+    }
+
+    #[test]
+    fn parses_nested_sections() {
+        assert_eq!(
+            parts("{country_code}[ {city}[ ({postal})]]"),
+            [
+                Part::Field(Field::CountryCode),
+                Part::Section(vec![
+                    text(" "),
+                    Part::Field(Field::City),
+                    Part::Section(vec![text(" ("), Part::Field(Field::Postal), text(")")]),
+                ]),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_unclosed_and_unmatched_tags() {
+        for (template, error) in [
+            ("{city", "unclosed {"),
+            ("[ {city}", "unclosed ["),
+            ("[[{city}]", "unclosed ["),
+            ("{city}]", "unmatched ]"),
+            ("{town}", "unknown field {town}"),
+            (r"{city}\", "trailing \\"),
+        ] {
+            let result = template.parse::<Template>();
+            assert!(
+                matches!(&result, Err(e) if e.ends_with(error)),
+                "{}: {:?}",
+                template,
+                result
+            );
+        }
+    }
+
+    #[test]
+    fn renders_sections_only_when_complete() {
+        let render = |template: &str| {
+            let template: Template = template.parse().unwrap();
+            template.render(&mountain_view())
+        };
+        assert_eq!(
+            render("{country_code}[ {subdivision}][ {city}][ | AS{asn} {org}]"),
+            "US California Mountain View"
+        );
+        // A missing field outside of a section renders as nothing
+        assert_eq!(render("{country_code} {org}"), "US");
+        // The inner section is dropped, the outer one kept
+        assert_eq!(
+            render("{country_code}[ {city}[ {postal}]]"),
+            "US Mountain View"
+        );
+        assert_eq!(render("{country_code}[ {postal}[ {city}]]"), "US");
+        assert_eq!(render(r"\[{country_code}\]"), "[US]");
+    }
+}
@@ -1,4 +1,5 @@
 use crate::geo::geodb::GeoLocation;
+use serde::{Deserialize, Serialize};
 use std::fmt::Display;
 
 #[derive(Debug, Clone, PartialEq)]
@@ -7,16 +8,92 @@ pub enum Token {
     IPv4(String, Option<GeoLocation>),
     IPv6(String, Option<GeoLocation>),
     Domain(String),
+    // Text matched by a user-defined pattern: pattern name, text, looked up value
+    Custom(String, String, Option<String>),
+}
+
+// The kind of a non-plain token, used before the token itself is built
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[serde(from = "String", into = "String")]
+pub enum TokenKind {
+    IPv4,
+    IPv6,
+    Domain,
+    // A user-defined pattern, identified by its name
+    Custom(String),
+}
+
+impl From<String> for TokenKind {
+    fn from(name: String) -> Self {
+        match name.as_str() {
+            "ipv4" => TokenKind::IPv4,
+            "ipv6" => TokenKind::IPv6,
+            "domain" => TokenKind::Domain,
+            _ => TokenKind::Custom(name),
+        }
+    }
+}
+
+impl From<TokenKind> for String {
+    fn from(kind: TokenKind) -> Self {
+        match kind {
+            TokenKind::IPv4 => "ipv4".to_string(),
+            TokenKind::IPv6 => "ipv6".to_string(),
+            TokenKind::Domain => "domain".to_string(),
+            TokenKind::Custom(name) => name,
+        }
+    }
+}
+
+impl TokenKind {
+    // Get the priority of the token kind
+    pub fn priority(&self) -> u8 {
+        match self {
+            TokenKind::Custom(_) => 4, // User-defined patterns are the most specific
+            TokenKind::IPv4 => 3,      // The highest built-in priority
+            TokenKind::IPv6 => 2,      // The second highest priority
+            TokenKind::Domain => 1,    // The lowest priority
+        }
+    }
+}
+
+// A set of token kinds, every kind belongs to the default set
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq)]
+#[serde(transparent)]
+pub struct KindSet(Option<Vec<TokenKind>>);
+
+impl KindSet {
+    pub fn all() -> Self {
+        Self(None)
+    }
+
+    pub fn only(kinds: Vec<TokenKind>) -> Self {
+        Self(Some(kinds))
+    }
+
+    pub fn contains(&self, kind: &TokenKind) -> bool {
+        self.0.as_ref().map_or(true, |kinds| kinds.contains(kind))
+    }
+
+    // The listed kinds, empty for the default set
+    pub fn kinds(&self) -> &[TokenKind] {
+        self.0.as_deref().unwrap_or_default()
+    }
 }
 
 impl Token {
     // Get the priority of the token
     pub fn priority(&self) -> u8 {
+        self.kind().map_or(0, |kind| kind.priority()) // Plain has no priority
+    }
+
+    pub fn kind(&self) -> Option<TokenKind> {
         match self {
-            Token::IPv4(_, _) => 3, // The highest priority
-            Token::IPv6(_, _) => 2, // The second highest priority
-            Token::Domain(_) => 1,  // The lowest priority
-            Token::Plain(_) => 0,   // No priority
+            Token::IPv4(_, _) => Some(TokenKind::IPv4),
+            Token::IPv6(_, _) => Some(TokenKind::IPv6),
+            Token::Domain(_) => Some(TokenKind::Domain),
+            Token::Custom(name, _, _) => Some(TokenKind::Custom(name.clone())),
+            Token::Plain(_) => None,
         }
     }
 }
@@ -34,6 +111,7 @@ impl Display for Token {
             Token::IPv4(addr, _) => write!(f, "{} [IPv4]", addr),
             Token::IPv6(addr, _) => write!(f, "{} [IPv6]", addr),
             Token::Domain(domain) => write!(f, "{} [Domain]", domain),
+            Token::Custom(name, text, _) => write!(f, "{} [{}]", text, name),
         }
     }
 }
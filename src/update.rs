@@ -0,0 +1,393 @@
+use std::{
+    fs::{self, File},
+    io::{Read, Write},
+    net::TcpStream,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use flate2::read::GzDecoder;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    diag,
+    error::{NaliError, Result},
+};
+
+const MAX_REDIRECTS: usize = 5;
+const HTTP_TIMEOUT: Duration = Duration::from_secs(60);
+
+fn fetch_error(url: &str, reason: impl std::fmt::Display) -> NaliError {
+    NaliError::Database(format!("Failed to fetch {}: {}", url, reason))
+}
+
+// Download `url`, only `file://` and plain `http://` URLs are supported so an
+// internal mirror or a local copy can stand in for the upstream source
+pub fn fetch(url: &str) -> Result<Vec<u8>> {
+    let mut url = url.to_string();
+    for _ in 0..=MAX_REDIRECTS {
+        if let Some(path) = url.strip_prefix("file://") {
+            return fs::read(path).map_err(|e| fetch_error(&url, e));
+        }
+        if url.starts_with("https://") {
+            return Err(fetch_error(
+                &url,
+                "https is not supported, use a plain http:// mirror or a file:// URL",
+            ));
+        }
+        match fetch_http(&url)? {
+            HttpResponse::Body(body) => return Ok(body),
+            HttpResponse::Redirect(location) => {
+                diag::info(format!("{} redirects to {}", url, location));
+                url = location;
+            }
+        }
+    }
+    Err(fetch_error(&url, "too many redirects"))
+}
+
+enum HttpResponse {
+    Body(Vec<u8>),
+    Redirect(String),
+}
+
+// A minimal HTTP/1.0 GET, the server closes the connection after the body
+fn fetch_http(url: &str) -> Result<HttpResponse> {
+    let rest = url
+        .strip_prefix("http://")
+        .ok_or_else(|| fetch_error(url, "unsupported URL scheme"))?;
+    let (authority, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/"),
+    };
+    let address = if authority.contains(':') {
+        authority.to_string()
+    } else {
+        format!("{}:80", authority)
+    };
+
+    let mut stream = TcpStream::connect(&address).map_err(|e| fetch_error(url, e))?;
+    stream
+        .set_read_timeout(Some(HTTP_TIMEOUT))
+        .map_err(|e| fetch_error(url, e))?;
+    write!(
+        stream,
+        "GET {} HTTP/1.0\r\nHost: {}\r\nUser-Agent: nali/{}\r\nConnection: close\r\n\r\n",
+        path,
+        authority,
+        env!("CARGO_PKG_VERSION")
+    )
+    .map_err(|e| fetch_error(url, e))?;
+    let mut response = Vec::new();
+    stream
+        .read_to_end(&mut response)
+        .map_err(|e| fetch_error(url, e))?;
+
+    let header_end = response
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .ok_or_else(|| fetch_error(url, "malformed HTTP response"))?;
+    let header = String::from_utf8_lossy(&response[..header_end]).into_owned();
+    let mut lines = header.lines();
+    let status: u16 = lines
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|code| code.parse().ok())
+        .ok_or_else(|| fetch_error(url, "malformed HTTP status line"))?;
+    match status {
+        200 => Ok(HttpResponse::Body(response.split_off(header_end + 4))),
+        301 | 302 | 303 | 307 | 308 => {
+            let location = lines
+                .filter_map(|line| line.split_once(':'))
+                .find(|(name, _)| name.trim().eq_ignore_ascii_case("location"))
+                .map(|(_, value)| value.trim().to_string())
+                .ok_or_else(|| fetch_error(url, "redirect without a location"))?;
+            // Resolve a path-only location against the current server
+            Ok(HttpResponse::Redirect(if location.starts_with('/') {
+                format!("http://{}{}", authority, location)
+            } else {
+                location
+            }))
+        }
+        _ => Err(fetch_error(url, format!("HTTP status {}", status))),
+    }
+}
+
+// The checksum in a `sha256sum`-style sidecar, `<hex digest>  <file name>`
+fn parse_sha256(url: &str, sidecar: &[u8]) -> Result<String> {
+    let digest = String::from_utf8_lossy(sidecar)
+        .split_whitespace()
+        .next()
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    if digest.len() != 64 || !digest.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(NaliError::Database(format!(
+            "{} does not contain a SHA-256 digest",
+            url
+        )));
+    }
+    Ok(digest)
+}
+
+pub fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+// Check `data` against the digest published at `sha256_url`
+pub fn verify_sha256(data: &[u8], sha256_url: &str) -> Result<()> {
+    let expected = parse_sha256(sha256_url, &fetch(sha256_url)?)?;
+    let actual = sha256_hex(data);
+    if actual != expected {
+        return Err(NaliError::Database(format!(
+            "Checksum mismatch: expected {}, got {}",
+            expected, actual
+        )));
+    }
+    Ok(())
+}
+
+// Take the database out of a download: the entry named `file_name` (or the only
+// `.mmdb` entry) of a `.tar.gz`, the content of a `.gz`, or the download itself
+pub fn extract(data: Vec<u8>, url: &str, file_name: &str) -> Result<Vec<u8>> {
+    let corrupt =
+        |e: std::io::Error| NaliError::Database(format!("Failed to extract {}: {}", url, e));
+    if url.ends_with(".tar.gz") || url.ends_with(".tgz") {
+        let mut archive = tar::Archive::new(GzDecoder::new(data.as_slice()));
+        let mut fallback = None;
+        for entry in archive.entries().map_err(corrupt)? {
+            let mut entry = entry.map_err(corrupt)?;
+            let path = entry.path().map_err(corrupt)?.into_owned();
+            let name = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .unwrap_or("");
+            let exact = name == file_name;
+            if exact || (fallback.is_none() && name.ends_with(".mmdb")) {
+                let mut content = Vec::new();
+                entry.read_to_end(&mut content).map_err(corrupt)?;
+                if exact {
+                    return Ok(content);
+                }
+                fallback = Some(content);
+            }
+        }
+        return fallback
+            .ok_or_else(|| NaliError::Database(format!("{} does not contain {}", url, file_name)));
+    }
+    if url.ends_with(".gz") {
+        let mut content = Vec::new();
+        GzDecoder::new(data.as_slice())
+            .read_to_end(&mut content)
+            .map_err(corrupt)?;
+        return Ok(content);
+    }
+    Ok(data)
+}
+
+// Replace `target` with `data` in one step, once `check` accepts the new file.
+// The new file is written next to the target so the rename never crosses filesystems.
+pub fn install(data: &[u8], target: &Path, check: impl Fn(&Path) -> Result<()>) -> Result<()> {
+    let file_name = target
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| NaliError::Config(format!("Invalid database path {}", target.display())))?;
+    let dir = target.parent().unwrap_or(Path::new("."));
+    fs::create_dir_all(dir)?;
+    let temp: PathBuf = dir.join(format!(".{}.{}.tmp", file_name, std::process::id()));
+
+    let result = (|| {
+        let mut file = File::create(&temp)?;
+        file.write_all(data)?;
+        file.sync_all()?;
+        check(&temp)?;
+        fs::rename(&temp, target)?;
+        Ok(())
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader},
+        net::TcpListener,
+        thread,
+    };
+
+    use flate2::{write::GzEncoder, Compression};
+
+    use super::*;
+    use crate::{commands, config::NaliConfig};
+
+    // An empty directory for one test
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("nali-test-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn tar_gz(entries: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (path, data) in entries {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, path, *data).unwrap();
+        }
+        gzip(&builder.into_inner().unwrap())
+    }
+
+    // Serve `responses` to one connection each, ignoring the requests
+    fn serve(responses: Vec<String>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            for response in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = BufReader::new(stream.try_clone().unwrap());
+                let mut line = String::new();
+                while request.read_line(&mut line).unwrap() > 2 {
+                    line.clear();
+                }
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+        });
+        format!("http://{}", address)
+    }
+
+    fn redirect(location: &str) -> String {
+        format!("HTTP/1.0 302 Found\r\nLocation: {}\r\n\r\n", location)
+    }
+
+    #[test]
+    fn extracts_the_named_tar_gz_entry() {
+        let archive = tar_gz(&[
+            ("GeoLite2-City_20240101/COPYRIGHT.txt", b"copyright"),
+            ("GeoLite2-City_20240101/GeoLite2-ASN.mmdb", b"asn"),
+            ("GeoLite2-City_20240101/GeoLite2-City.mmdb", b"city"),
+        ]);
+        let extracted = extract(archive.clone(), "http://x/db.tar.gz", "GeoLite2-City.mmdb");
+        assert_eq!(extracted.unwrap(), b"city");
+        // Without an exact match the first `.mmdb` entry is used
+        let extracted = extract(archive, "http://x/db.tgz", "city.mmdb");
+        assert_eq!(extracted.unwrap(), b"asn");
+
+        let archive = tar_gz(&[("README", b"readme")]);
+        assert!(extract(archive, "http://x/db.tar.gz", "GeoLite2-City.mmdb").is_err());
+    }
+
+    #[test]
+    fn extracts_a_plain_gz() {
+        let extracted = extract(gzip(b"city"), "http://x/City.mmdb.gz", "City.mmdb");
+        assert_eq!(extracted.unwrap(), b"city");
+        assert!(extract(b"city".to_vec(), "http://x/City.mmdb.gz", "City.mmdb").is_err());
+        // Anything else is the database itself
+        let extracted = extract(b"city".to_vec(), "http://x/City.mmdb", "City.mmdb");
+        assert_eq!(extracted.unwrap(), b"city");
+    }
+
+    // Run `nali db update` from `download` with `sidecar` as its checksum,
+    // returning the result and the files left next to the database
+    fn update(name: &str, download: &[u8], sidecar: &str) -> (Result<()>, PathBuf) {
+        let dir = temp_dir(name);
+        fs::write(dir.join("City.mmdb"), b"old").unwrap();
+        fs::write(dir.join("download.mmdb"), download).unwrap();
+        fs::write(dir.join("download.mmdb.sha256"), sidecar).unwrap();
+        let config = dir.join("config.toml");
+        fs::write(
+            &config,
+            format!(
+                "[geodb.geo_lite2]\npath = \"City.mmdb\"\nupdate_url = \"file://{}\"\n",
+                dir.join("download.mmdb").display()
+            ),
+        )
+        .unwrap();
+        let nali_config = NaliConfig::load(Some(&config)).unwrap();
+        (commands::db_update(&nali_config, None), dir)
+    }
+
+    fn file_names(dir: &Path) -> Vec<String> {
+        let mut names = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn a_checksum_mismatch_keeps_the_database() {
+        let sidecar = format!("{}  download.mmdb\n", sha256_hex(b"something else"));
+        let (result, dir) = update("mismatch", b"new", &sidecar);
+        assert!(matches!(result, Err(NaliError::Database(e)) if e.contains("Checksum mismatch")));
+        assert_eq!(fs::read(dir.join("City.mmdb")).unwrap(), b"old");
+        assert_eq!(
+            file_names(&dir),
+            [
+                "City.mmdb",
+                "config.toml",
+                "download.mmdb",
+                "download.mmdb.sha256"
+            ]
+        );
+    }
+
+    #[test]
+    fn a_download_that_does_not_open_is_rolled_back() {
+        let sidecar = format!("{}  download.mmdb\n", sha256_hex(b"new"));
+        let (result, dir) = update("unopenable", b"new", &sidecar);
+        assert!(matches!(result, Err(NaliError::Database(e)) if e.contains("does not open")));
+        assert_eq!(fs::read(dir.join("City.mmdb")).unwrap(), b"old");
+        assert_eq!(
+            file_names(&dir),
+            [
+                "City.mmdb",
+                "config.toml",
+                "download.mmdb",
+                "download.mmdb.sha256"
+            ]
+        );
+    }
+
+    #[test]
+    fn installs_once_the_check_passes() {
+        let dir = temp_dir("install");
+        let target = dir.join("City.mmdb");
+        fs::write(&target, b"old").unwrap();
+        install(b"new", &target, |path| {
+            assert_ne!(path, target);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(fs::read(&target).unwrap(), b"new");
+        assert_eq!(file_names(&dir), ["City.mmdb"]);
+    }
+
+    #[test]
+    fn follows_redirects() {
+        let body = "HTTP/1.0 200 OK\r\nContent-Length: 4\r\n\r\ncity".to_string();
+        let base = serve(vec![redirect("/moved"), body]);
+        assert_eq!(fetch(&format!("{}/db", base)).unwrap(), b"city");
+    }
+
+    #[test]
+    fn stops_after_too_many_redirects() {
+        let base = serve(vec![redirect("/loop"); MAX_REDIRECTS + 1]);
+        let result = fetch(&format!("{}/loop", base));
+        assert!(matches!(result, Err(NaliError::Database(e)) if e.contains("too many redirects")));
+    }
+}